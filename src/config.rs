@@ -1,3 +1,5 @@
+use super::delivery::Target;
+use super::retention::Retention;
 use super::settings::*;
 
 /// This structure defines the feed-level config.
@@ -20,6 +22,30 @@ pub struct Config {
         default = "Settings::default_false"
     )]
     pub inline_image_as_data: bool,
+    /// When set, contains the IMAP flags (like `\Seen`) and keywords (like `$Important`) set on written messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+    /// When set, contains the way messages are delivered (IMAP when not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Target>,
+    /// When set, old messages are removed from folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+    /// When set, contains the name of the account (defined in settings `accounts`) messages are written to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// When set to true, entries changed since they were written are written again (replacing the previous version when possible)
+    #[serde(
+        skip_serializing_if = "Settings::is_false",
+        default = "Settings::default_false"
+    )]
+    pub track_updates: bool,
+    /// When set to true, subject of entries written again is prefixed with `[updated]`
+    #[serde(
+        skip_serializing_if = "Settings::is_false",
+        default = "Settings::default_false"
+    )]
+    pub mark_updates: bool,
 }
 
 impl Config {
@@ -30,6 +56,12 @@ impl Config {
             folder: None,
             inline_image_as_data: false,
             from: None,
+            flags: None,
+            delivery: None,
+            retention: None,
+            account: None,
+            track_updates: false,
+            mark_updates: false,
         }
     }
 
@@ -57,6 +89,12 @@ impl Config {
             && config.folder.is_none()
             && config.from.is_none()
             && !config.inline_image_as_data
+            && config.flags.is_none()
+            && config.delivery.is_none()
+            && config.retention.is_none()
+            && config.account.is_none()
+            && !config.track_updates
+            && !config.mark_updates
     }
 
     /// Clear all content from this config excepted email address
@@ -78,6 +116,45 @@ impl Config {
             .unwrap_or_else(|| default.clone().folder.unwrap_or_else(|| "".to_owned()))
     }
 
+    /// Get the IMAP flags for that feed, be they defined locally or from the default config
+    pub fn get_flags(&self, default: &Config) -> Vec<String> {
+        self.clone()
+            .flags
+            .unwrap_or_else(|| default.clone().flags.unwrap_or_default())
+    }
+
+    /// Get the delivery target for that feed, be it defined locally or from the default config
+    pub fn get_delivery(&self, default: &Config) -> Target {
+        self.clone()
+            .delivery
+            .unwrap_or_else(|| default.clone().delivery.unwrap_or(Target::Imap))
+    }
+
+    /// Get the retention for that feed, be it defined locally or from the default config
+    pub fn get_retention(&self, default: &Config) -> Option<Retention> {
+        self.clone()
+            .retention
+            .or_else(|| default.clone().retention)
+    }
+
+    /// Get the account for that feed, be it defined locally or from the default config.
+    /// None designates the default account
+    pub fn get_account(&self, default: &Config) -> Option<String> {
+        self.clone()
+            .account
+            .or_else(|| default.clone().account)
+    }
+
+    /// Check if changed entries of that feed are written again, be it defined locally or in the default config
+    pub fn get_track_updates(&self, default: &Config) -> bool {
+        self.track_updates || default.track_updates
+    }
+
+    /// Check if entries written again are marked as updated, be it defined locally or in the default config
+    pub fn get_mark_updates(&self, default: &Config) -> bool {
+        self.mark_updates || default.mark_updates
+    }
+
     /// Compute an inline flag by resolving the two flags with this struct inline images status
     pub fn inline(&self, inline:bool, do_not_inline:bool)->bool {
         if self.inline_image_as_data {
@@ -0,0 +1,240 @@
+use chrono::NaiveDateTime;
+use custom_error::custom_error;
+use directories::BaseDirs;
+use imap::types::Uid;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::dry_run::DryRunDelivery;
+use super::feed::Feed;
+use super::maildir::MaildirDelivery;
+use super::mbox::MboxDelivery;
+use super::outbox::*;
+use super::session_pool::{SessionPool, SessionPools};
+use super::settings::Settings;
+use super::smtp::SmtpDelivery;
+
+custom_error!{pub UndeliverableMessage
+    Imap{ source:imap::error::Error } = "IMAP server didn't accept message",
+    Smtp{ reason:String } = "SMTP server didn't accept message: {reason}",
+    NotConfigured{ target:String } = "{target} delivery is used, but not configured in settings",
+    IO{ source:std::io::Error } = "Unable to write message"
+}
+
+/// How messages are delivered
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum Target {
+    /// Messages are directly written in IMAP folders
+    Imap,
+    /// Messages are sent to the feed email address using the SMTP server defined in settings
+    Smtp,
+    /// Messages are written in the Maildir++ directory at the given path
+    Maildir(PathBuf),
+    /// Messages are appended to mbox files (one per folder) in the directory at the given path
+    Mbox(PathBuf),
+}
+
+/// Replaces the leading `~` of path with user home directory
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), BaseDirs::new()) {
+        (Ok(relative), Some(dirs)) => dirs.home_dir().join(relative),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A fully built message, and all the informations required to deliver it
+pub struct Envelope<'a> {
+    /// Message-ID of message
+    pub message_id: &'a str,
+    /// Title of the entry
+    pub title: &'a str,
+    /// Folder in which message should be written (when delivery supports folders)
+    pub folder: &'a str,
+    /// Email address message is sent to (when delivery sends messages)
+    pub recipient: &'a str,
+    /// Full RFC 5322 message, as built by [build_message](crate::message::Message::build_message)
+    pub content: &'a str,
+    /// IMAP flags of message
+    pub flags: &'a [String],
+    /// Date of message (if any)
+    pub date: Option<NaiveDateTime>,
+}
+
+/// Where a message has been written on an IMAP server
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Location {
+    pub folder: String,
+    /// UIDVALIDITY of folder when message was written. When it changes, uid is no more valid.
+    pub uid_validity: u32,
+    pub uid: Uid,
+}
+
+/// Common interface of all the ways messages can be delivered.
+/// As messages are delivered from all rayon workers, implementations have to be shareable.
+pub trait Delivery: Sync {
+    /// Checks if the message with the given Message-ID has already been delivered.
+    /// Only deliveries able to read what they wrote can implement it.
+    fn contains(&self, _folder: &str, _message_id: &str) -> Result<bool, UndeliverableMessage> {
+        Ok(false)
+    }
+
+    /// Delivers the given message
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage>;
+
+    /// Finds where the given (already delivered) message has been written.
+    /// Only deliveries able to read what they wrote can implement it.
+    fn locate(&self, _envelope: &Envelope) -> Result<Option<Location>, UndeliverableMessage> {
+        Ok(None)
+    }
+
+    /// Removes the message written at the given location (typically because a newer version of it has been delivered)
+    fn remove(&self, _location: &Location) -> Result<(), UndeliverableMessage> {
+        Ok(())
+    }
+}
+
+/// Writes messages in IMAP folders, putting them in outbox when IMAP server is unavailable
+pub struct ImapDelivery<'a> {
+    account: Option<String>,
+    pool: &'a SessionPool<'a>,
+    outbox: &'a Outbox,
+}
+
+impl<'a> ImapDelivery<'a> {
+    pub fn new(account: Option<String>, pool: &'a SessionPool<'a>, outbox: &'a Outbox) -> ImapDelivery<'a> {
+        ImapDelivery { account, pool, outbox }
+    }
+}
+
+impl Delivery for ImapDelivery<'_> {
+    fn contains(&self, folder: &str, message_id: &str) -> Result<bool, UndeliverableMessage> {
+        if self.pool.email().check_duplicates {
+            Ok(self.pool.contains_message(folder, message_id)?)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Appends message to its folder.
+    /// When IMAP server was unable to receive the message, it is put in outbox to be written later.
+    /// When server refused it (which it would do again), it is set aside in outbox.
+    /// An error is only returned when even that failed.
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage> {
+        // Folders built from templates can't be created before run, so they're created when first used
+        if let Err(e) = self.pool.ensure_folders(&[envelope.folder.to_owned()]) {
+            warn!("{}\nUnable to create mailbox {}", e, envelope.folder);
+        }
+        match self.pool.append(&envelope.folder, &envelope.content, envelope.flags, envelope.date) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let queued = QueuedMessage {
+                    folder: envelope.folder.to_owned(),
+                    content: envelope.content.to_owned(),
+                    flags: envelope.flags.to_vec(),
+                    date: envelope.date,
+                    account: self.account.clone(),
+                };
+                let kept = if SessionPool::is_connection_broken(&e) {
+                    error!("{}\nUnable to write message in mailbox {}. It will be put in outbox", e, envelope.folder);
+                    self.outbox.enqueue(&queued)
+                } else {
+                    error!("{}\nServer refused message in mailbox {}", e, envelope.folder);
+                    self.outbox.set_aside(&queued)
+                };
+                match kept {
+                    Ok(_) => Ok(()),
+                    Err(queue_error) => {
+                        error!("{}\nUnable to put message in outbox", queue_error);
+                        Err(UndeliverableMessage::Imap { source: e })
+                    }
+                }
+            }
+        }
+    }
+
+    /// As IMAP library doesn't give access to APPENDUID response, message is searched by its Message-ID.
+    /// Location is only useful to remove message, which requires `UID EXPUNGE` (from UIDPLUS extension),
+    /// so servers without UIDPLUS are not searched.
+    fn locate(&self, envelope: &Envelope) -> Result<Option<Location>, UndeliverableMessage> {
+        if !self.pool.has_capability("UIDPLUS")? {
+            debug!("{} doesn't support UIDPLUS. Written messages are not located", self.pool.email().server);
+            return Ok(None);
+        }
+        let found = self.pool.locate_message(envelope.folder, envelope.message_id)?;
+        Ok(found.map(|(uid_validity, uid)| Location { folder: envelope.folder.to_owned(), uid_validity, uid }))
+    }
+
+    fn remove(&self, location: &Location) -> Result<(), UndeliverableMessage> {
+        if !self.pool.remove_message(&location.folder, location.uid_validity, location.uid)? {
+            warn!("Folder {} has been recreated since message {} was written. It won't be removed", location.folder, location.uid);
+        }
+        Ok(())
+    }
+}
+
+/// All the deliveries available during a run
+pub struct Deliveries<'a> {
+    /// IMAP deliveries of all accounts
+    pub imap: HashMap<Option<String>, ImapDelivery<'a>>,
+    pub smtp: Option<SmtpDelivery>,
+    pub maildirs: HashMap<PathBuf, MaildirDelivery>,
+    pub mboxes: HashMap<PathBuf, MboxDelivery>,
+    /// When set, all messages are written there instead of being delivered
+    pub dry_run: Option<DryRunDelivery>,
+    /// IMAP accounts for which images are not inlined (because their quota is nearly exhausted)
+    pub without_inlined_images: HashSet<Option<String>>,
+}
+
+impl<'a> Deliveries<'a> {
+    /// Creates all the deliveries used by the given feeds
+    pub fn new(settings: &Settings, feeds: &[Feed], pools: &'a SessionPools<'a>, outbox: &'a Outbox) -> Deliveries<'a> {
+        let smtp = settings.smtp.as_ref()
+            .and_then(|smtp| SmtpDelivery::new(smtp, &settings.email.secrets)
+                .inspect_err(|e| error!("Unable to use SMTP server {} due to {}", smtp.server, e))
+                .ok());
+        let mut maildirs = HashMap::new();
+        let mut mboxes = HashMap::new();
+        for feed in feeds {
+            match feed.config.get_delivery(&settings.config) {
+                Target::Maildir(path) => {
+                    maildirs.entry(path.clone()).or_insert_with(|| MaildirDelivery::new(&path));
+                },
+                Target::Mbox(path) => {
+                    mboxes.entry(path.clone()).or_insert_with(|| MboxDelivery::new(&path));
+                },
+                _ => {},
+            }
+        }
+        Deliveries {
+            imap: pools.iter()
+                .map(|(account, pool)| (account.clone(), ImapDelivery::new(account.clone(), pool, outbox)))
+                .collect(),
+            smtp,
+            maildirs,
+            mboxes,
+            dry_run: None,
+            without_inlined_images: HashSet::new(),
+        }
+    }
+
+    /// Get the delivery to use for the given target (and account, when target is IMAP)
+    pub fn get(&self, target: &Target, account: &Option<String>) -> Result<&dyn Delivery, UndeliverableMessage> {
+        if let Some(dry_run) = &self.dry_run {
+            return Ok(dry_run);
+        }
+        match target {
+            Target::Imap => self.imap.get(account)
+                .map(|imap| imap as &dyn Delivery)
+                .ok_or(UndeliverableMessage::NotConfigured { target: format!("IMAP account {:?}", account) }),
+            Target::Smtp => self.smtp.as_ref()
+                .map(|smtp| smtp as &dyn Delivery)
+                .ok_or(UndeliverableMessage::NotConfigured { target: "SMTP".to_owned() }),
+            Target::Maildir(path) => self.maildirs.get(path)
+                .map(|maildir| maildir as &dyn Delivery)
+                .ok_or(UndeliverableMessage::NotConfigured { target: format!("Maildir {}", path.to_string_lossy()) }),
+            Target::Mbox(path) => self.mboxes.get(path)
+                .map(|mbox| mbox as &dyn Delivery)
+                .ok_or(UndeliverableMessage::NotConfigured { target: format!("mbox {}", path.to_string_lossy()) }),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tests_bin::unit_tests;
+
+use super::delivery::*;
+
+/// Message written during a dry run, as listed in index
+struct Written {
+    folder: String,
+    file: String,
+    title: String,
+}
+
+/// Writes messages as `.eml` files (in one directory per folder) instead of delivering them,
+/// to see exactly what would be delivered.
+#[unit_tests("dry_run.rs")]
+pub struct DryRunDelivery {
+    root: PathBuf,
+    written: Mutex<Vec<Written>>,
+}
+
+impl DryRunDelivery {
+    pub fn new(root: &Path) -> DryRunDelivery {
+        DryRunDelivery {
+            root: expand_home(root),
+            written: Mutex::new(vec![]),
+        }
+    }
+
+    /// Name of the file used for message : the local part of its Message-ID, which is stable for an entry
+    fn file_name(message_id: &str) -> String {
+        let name: String = message_id
+            .split('@')
+            .next()
+            .unwrap_or(message_id)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        format!("{}.eml", name)
+    }
+
+    /// Writes an `index.html` file linking all the written messages, sorted by folder
+    pub fn write_index(&self) -> io::Result<PathBuf> {
+        let mut written = self.written.lock().unwrap();
+        written.sort_by(|first, second| first.folder.cmp(&second.folder));
+        let items: Vec<String> = written
+            .iter()
+            .map(|message| format!(
+                "<li>{} : <a href=\"{}\">{}</a></li>",
+                escape(&message.folder),
+                escape(&message.file),
+                escape(&message.title)
+            ))
+            .collect();
+        let index = self.root.join("index.html");
+        fs::create_dir_all(&self.root)?;
+        fs::write(&index, format!(
+            "<html>\n<head><meta charset=\"utf-8\"><title>rrss2imap dry run</title></head>\n<body>\n<h1>{} messages</h1>\n<ul>\n{}\n</ul>\n</body>\n</html>\n",
+            items.len(),
+            items.join("\n")
+        ))?;
+        Ok(index)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Delivery for DryRunDelivery {
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage> {
+        let folder = envelope.folder.trim_matches('/');
+        let directory = self.root.join(folder);
+        fs::create_dir_all(&directory)?;
+        let name = DryRunDelivery::file_name(envelope.message_id);
+        fs::write(directory.join(&name), envelope.content)?;
+        info!("Message {} written to {}", envelope.title, directory.join(&name).to_string_lossy());
+        self.written.lock().unwrap().push(Written {
+            folder: folder.to_owned(),
+            file: if folder.is_empty() { name } else { format!("{}/{}", folder, name) },
+            title: envelope.title.to_owned(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use tests_bin::unit_tests;
+
+use super::delivery::Location;
+use super::message::Message;
+
+/// What is known of an entry already written, allowing to detect entries changed since
+/// (only maintained for feeds tracking updates)
+#[unit_tests("entry_state.rs")]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct EntryState {
+    /// Date of entry when it was written
+    pub updated: NaiveDateTime,
+    /// Hash of entry title and content when it was written
+    pub hash: String,
+    /// Where entry has been written, when delivery allows to find it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub location: Option<Location>,
+}
+
+impl EntryState {
+    /// State of the given message, once written at the given location
+    pub fn of(message: &Message, location: Option<Location>) -> EntryState {
+        EntryState {
+            updated: message.last_date,
+            hash: message.content_hash(),
+            location,
+        }
+    }
+
+    /// Check if the given message is a newer version of this entry : its date must not have gone back in time,
+    /// and either its content or its date must have changed.
+    /// Date is only considered when it is the entry one (RSS items without dates get the feed one, which changes
+    /// each time feed is built).
+    pub fn has_changed(&self, message: &Message) -> bool {
+        message.last_date >= self.updated
+            && (self.hash != message.content_hash() || (message.dated && message.last_date > self.updated))
+    }
+}
@@ -9,11 +9,11 @@ use super::store::Store;
 
 use treexml::*;
 
-pub fn export(to_file: &PathBuf, to_store: &Store) {
+pub fn export(to_file: &PathBuf, to_store: &Store) -> std::io::Result<()> {
     // First group feeds per storage folder
     let grouped = group_feeds(to_store);
     // Then write this map of lists
-    write(to_file, grouped);
+    write(to_file, grouped)
 }
 
 fn group_feeds(to_store: &Store) -> HashMap<String, Vec<Feed>> {
@@ -31,7 +31,7 @@ fn group_feeds(to_store: &Store) -> HashMap<String, Vec<Feed>> {
     })
 }
 
-fn write(to_file: &PathBuf, to_store: HashMap<String, Vec<Feed>>) {
+fn write(to_file: &PathBuf, to_store: HashMap<String, Vec<Feed>>) -> std::io::Result<()> {
     //    warn!("exporting feeds {:?}", to_store);
     // Prepare the document by setting all boilerplate elements (root, head, body, ...)
     let mut root = Element::new("opml");
@@ -72,5 +72,4 @@ fn write(to_file: &PathBuf, to_store: HashMap<String, Vec<Feed>>) {
     let mut document = Document::new();
     document.root = Some(root);
     fs::write(to_file, format!("{}", document))
-        .unwrap_or_else(|_| panic!("Unable to write file {:?}", to_file));
 }
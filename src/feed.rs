@@ -1,8 +1,12 @@
 use chrono::{NaiveDateTime};
+use std::collections::BTreeMap;
 use tests_bin::unit_tests;
 
 use super::config::*;
+use super::delivery::{Deliveries, Location, Target};
+use super::entry_state::EntryState;
 
+use super::feed_errors::UnreadableFeed;
 use super::feed_reader::*;
 use super::settings::*;
 use super::syndication;
@@ -22,7 +26,10 @@ pub struct Feed {
     /// Last message stored in IMAP, allows to correctly process feeds even when no date is provided
     /// which, mind you, is totally possible according to RSS specification
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub last_message: Option<String>
+    pub last_message: Option<String>,
+    /// State of entries currently in feed, by entry id (only when feed tracks updates)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub entries: BTreeMap<String, EntryState>,
 }
 
 impl Feed {
@@ -66,9 +73,16 @@ impl Feed {
                 folder,
                 from: None,
                 inline_image_as_data: false,
+                flags: None,
+                delivery: None,
+                retention: None,
+                account: None,
+                track_updates: false,
+                mark_updates: false,
             },
             last_updated: Feed::at_epoch(),
-            last_message: None
+            last_message: None,
+            entries: BTreeMap::new()
         }
     }
 
@@ -80,9 +94,16 @@ impl Feed {
                 folder: destination,
                 from: None,
                 inline_image_as_data: inline,
+                flags: None,
+                delivery: None,
+                retention: None,
+                account: None,
+                track_updates: false,
+                mark_updates: false,
             },
             last_updated: Feed::at_epoch(),
-            last_message: None
+            last_message: None,
+            entries: BTreeMap::new()
         }
     }
 
@@ -93,44 +114,44 @@ impl Feed {
     /**
      * Read the feed and produce the list of messages to write later
      */
-    pub fn read(&self, index:usize, count:&usize) -> Vec<Message> {
+    pub fn read(&self, index:usize, count:&usize) -> Result<Vec<Message>, UnreadableFeed> {
         info!("Reading feed {}/{} from {}", index+1, count, self.url);
-        match ureq::get(&self.url).call() {
-            Ok(response) => match response.into_string() {
-                Ok(text) => return self.read_response_text(text),
-                Err(e) => error!("There is no text at {} due to error {}", &self.url, e),
-            },
-            Err(e) => error!("Unable to get {} due to {}.\nTODO Add better http response analysis !", &self.url, e),
-        }
-        vec![]
+        let response = ureq::get(&self.url).call()
+            .map_err(|e| UnreadableFeed::CantGet { url: self.url.clone(), reason: e.to_string() })?;
+        let text = response.into_string()
+            .map_err(|e| UnreadableFeed::NoText { url: self.url.clone(), reason: e.to_string() })?;
+        self.read_response_text(text)
     }
 
-    pub fn read_response_text(&self, text:String) -> Vec<Message> {
+    pub fn read_response_text(&self, text:String) -> Result<Vec<Message>, UnreadableFeed> {
         match text.parse::<syndication::Feed>() {
-            Ok(parsed) => {
-                return match parsed {
-                    syndication::Feed::Atom(atom_feed) => {
-                        AtomReader {}.read(self, &atom_feed)
-                    }
-                    syndication::Feed::RSS(rss_feed) => {
-                        RssReader {}.read(self, &rss_feed)
-                    }
-                }
-            }
-            Err(e) => error!("Content ar {} is neither Atom, nor RSS {}.\nTODO check real content type to help user.", &self.url, e),
+            Ok(syndication::Feed::Atom(atom_feed)) => Ok(AtomReader {}.read(self, &atom_feed)),
+            Ok(syndication::Feed::RSS(rss_feed)) => Ok(RssReader {}.read(self, &rss_feed)),
+            Err(e) => Err(UnreadableFeed::NeitherAtomNorRss { url: self.url.clone(), reason: e.to_string() }),
         }
-        vec![]
     }
 
-    pub fn process_message(&self, settings:&Settings, message:&Message)->Message {
-        Message {
+    /// Check if images of feed messages are inlined : when configured so, unless the quota of the IMAP account
+    /// messages are written to is nearly exhausted
+    pub fn inline_images(&self, settings:&Settings, deliveries:&Deliveries) -> bool {
+        let configured = self.config.inline_image_as_data || settings.config.inline_image_as_data;
+        configured && !(self.config.get_delivery(&settings.config) == Target::Imap
+            && deliveries.without_inlined_images.contains(&self.config.get_account(&settings.config)))
+    }
+
+    pub fn process_message(&self, settings:&Settings, deliveries:&Deliveries, message:&Message)->Result<Message, UnprocessableMessage> {
+        Ok(Message {
             authors: message.authors.clone(),
-            content: Message::get_processed_content(&message.content, self, settings).unwrap(),
+            content: Message::get_processed_content(&message.content, self.inline_images(settings, deliveries))?,
             id: message.id.clone(),
             last_date: message.last_date,
+            dated: message.dated,
             links: message.links.clone(),
             title: message.title.clone(),
-        }
+            feed_title: message.feed_title.clone(),
+            categories: message.categories.clone(),
+            revision: message.revision.clone(),
+        })
     }
 
     /// Find in the given input feed the new messages
@@ -165,7 +186,13 @@ impl Feed {
         (head, tail, found)
     }
 
-    pub fn write_new_messages(&self, settings:&Settings, extracted:Vec<Message>)->Feed {
+    /// Write new messages of feed, and return the feed with an updated state, as well as the number of messages
+    /// that couldn't be delivered.
+    /// Messages are written from the oldest to the newest (messages IMAP refused being put in outbox),
+    /// and writing stops at the first message that could be neither written nor put in outbox (or which images couldn't be inlined).
+    /// This way, feed state only advances up to the last successfully written message, and undelivered ones
+    /// are retried on next run.
+    pub fn write_new_messages(&self, settings:&Settings, deliveries:&Deliveries, extracted:Vec<Message>)->(Feed, usize) {
         let sorted_messages = extracted;
         let (head, tail, found) = self.find_new_messages(sorted_messages.as_slice());
         let filtered_messages:&[Message] = if found {
@@ -174,37 +201,111 @@ impl Feed {
             sorted_messages.as_slice()
         };
 
-        // And write the messages into IMAP and the feed into JSON
-        let written_messages:Vec<Message> = filtered_messages.iter()
-            .map(|message| self.process_message(settings, message))
-            .inspect(|e| if !settings.do_not_save { e.write_to_imap(self, settings) } )
-            .collect();
-        let mut last_message:Option<&Message> = written_messages.iter()
-            // ok, there is a small problem here: if at least two elements have the same value - which is the case when feed
-            // elements have no dates - the LAST one is used (which is **not** what we want)
-            // see https://doc.rust-lang.org/std/iter/trait.Iterator.html#method.max_by_key
-            .max_by_key(|e| e.last_date.timestamp());
-        // So, to overcome last problem, if first filtered message has same date than last_message, we replace last by first
-        // As RSS feeds are supposed to put the latest emitted message in first position
-        match last_message {
-            Some(last) => if filtered_messages.len()>1 && filtered_messages[0].last_date==last.last_date {
-                last_message = Some(&filtered_messages[0]);
-            },
-            _ => {}
-        }
-        
         let mut returned = self.clone();
-        if settings.do_not_save {
+        if settings.do_not_save && deliveries.dry_run.is_none() {
             warn!("do_not_save is set. As a consequence, feed won't be updated");
-        } else {
-            match last_message {
-                Some(message) => {
-                    returned.last_updated = message.last_date;
-                    returned.last_message = Some(message.id.clone());
+            return (returned, 0);
+        }
+        let track_updates = self.config.get_track_updates(&settings.config);
+        // And deliver the messages and write the feed into JSON
+        // RSS feeds are supposed to put the latest emitted message in first position, so we write them in reverse order
+        let mut written_messages:Vec<&Message> = vec![];
+        for message in filtered_messages.iter().rev() {
+            // Entries already written are only written again (as updates) when they changed
+            if track_updates && self.entries.contains_key(&message.id) {
+                written_messages.push(message);
+                continue;
+            }
+            match self.process_message(settings, deliveries, message).and_then(|processed| processed.deliver(self, settings, deliveries)) {
+                Ok(location) => {
+                    if track_updates {
+                        returned.entries.insert(message.id.clone(), EntryState::of(message, location));
+                    }
+                    written_messages.push(message)
+                },
+                // Delivery (or image inlining) failed, so there is no need to try writing the other messages
+                Err(e) if e.is_temporary() => break,
+                // Message can't be built, and never will. So consider it as written (to not block feed forever)
+                Err(_) => written_messages.push(message),
+            }
+        }
+        let mut undelivered = filtered_messages.len() - written_messages.len();
+        if track_updates {
+            let already_read = if found { tail } else { sorted_messages.len() };
+            undelivered += self.write_updates(settings, deliveries, &sorted_messages, already_read, &mut returned.entries);
+        }
+        if undelivered > 0 {
+            warn!("{} messages of feed {} couldn't be written. They will be retried on next run", undelivered, self.url);
+        }
+        // When at least two elements have the same date - which is the case when feed elements have no dates - the LAST one
+        // is used (see https://doc.rust-lang.org/std/iter/trait.Iterator.html#method.max_by_key), which is the latest written one.
+        let last_message:Option<&&Message> = written_messages.iter()
+            .max_by_key(|e| e.last_date.timestamp());
+        if let Some(message) = last_message {
+            returned.last_updated = message.last_date;
+            returned.last_message = Some(message.id.clone());
+        }
+        (returned, undelivered)
+    }
+
+    /// Writes again the already written entries which changed since, replacing their previous version.
+    /// Entries read before updates were tracked (the ones after `already_read` position) get their state recorded,
+    /// and entries no more in feed are forgotten.
+    /// Returns the number of changed entries which couldn't be delivered (they're retried on next run).
+    fn write_updates(&self, settings:&Settings, deliveries:&Deliveries, messages:&[Message], already_read:usize,
+            entries:&mut BTreeMap<String, EntryState>)->usize {
+        let mut undelivered = 0;
+        for (position, message) in messages.iter().enumerate().rev() {
+            match self.entries.get(&message.id) {
+                Some(previous) if previous.has_changed(message) => {
+                    match self.write_update(settings, deliveries, message, previous) {
+                        Ok(location) => {
+                            entries.insert(message.id.clone(), EntryState::of(message, location));
+                        },
+                        Err(e) if e.is_temporary() => undelivered += 1,
+                        // Message can't be built, so this version is considered as written
+                        Err(_) => {
+                            entries.insert(message.id.clone(), EntryState::of(message, previous.location.clone()));
+                        },
+                    }
+                },
+                Some(_) => {},
+                None => if position >= already_read {
+                    entries.insert(message.id.clone(), EntryState::of(message, None));
                 },
-                _ => {}
             }
         }
-        returned
+        entries.retain(|id, _| messages.iter().any(|message| &message.id == id));
+        undelivered
+    }
+
+    /// Writes the new version of a changed entry, then removes the previous one (when locations of both are known).
+    /// Returns the location of the version to remove on next update.
+    fn write_update(&self, settings:&Settings, deliveries:&Deliveries, message:&Message, previous:&EntryState)
+            ->Result<Option<Location>, UnprocessableMessage> {
+        info!("Entry {} of feed {} changed. Writing it again", message.title, self.url);
+        let mut updated = self.process_message(settings, deliveries, message)?;
+        updated.revision = Some(format!("{} {}", message.content_hash(), message.last_date));
+        if self.config.get_mark_updates(&settings.config) {
+            updated.title = format!("[updated] {}", updated.title);
+        }
+        let location = updated.deliver(self, settings, deliveries)?;
+        match (&location, &previous.location) {
+            (Some(_), Some(old)) => {
+                let removed = deliveries
+                    .get(&self.config.get_delivery(&settings.config), &self.config.get_account(&settings.config))
+                    .and_then(|delivery| delivery.remove(old));
+                if let Err(e) = removed {
+                    warn!("{}\nUnable to remove previous version of entry {}", e, message.title);
+                }
+                Ok(location)
+            },
+            // New version can't be found (like when it went to outbox) : previous one is kept, to be removed by next update
+            (None, Some(_)) => {
+                warn!("New version of entry {} can't be located. Previous version is kept", message.title);
+                Ok(previous.location.clone())
+            },
+            (_, None) => Ok(location),
+        }
     }
 }
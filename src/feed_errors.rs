@@ -8,4 +8,10 @@ custom_error!{
     ChronoCantParse{source: chrono::ParseError} = "chrono can't parse date",
     NoDateFound = "absolutly no date field was found in feed",
     CantExtractImages{source: super::message::UnprocessableMessage} = "Seems like it was not possible to read message contained images"
-}
\ No newline at end of file
+}
+custom_error!{
+    pub UnreadableFeed
+    CantGet{url:String, reason:String} = "Unable to get {url} due to {reason}",
+    NoText{url:String, reason:String} = "There is no text at {url} due to {reason}",
+    NeitherAtomNorRss{url:String, reason:String} = "Content at {url} is neither Atom, nor RSS ({reason})"
+}
@@ -87,8 +87,12 @@ impl Reader<AtomEntry, AtomFeed> for AtomReader {
             content,
             id: entry.id().to_owned(),
             last_date,
+            dated: true,
             links: entry.links().iter().map(|l| l.href().to_owned()).collect(),
-            title: entry.title().as_str().to_string()
+            title: entry.title().as_str().to_string(),
+            feed_title: source.title().as_str().to_string(),
+            categories: entry.categories().iter().map(|c| c.term().to_owned()).collect(),
+            revision: None,
         };
         Ok(message)
     }
@@ -195,13 +199,19 @@ impl Reader<RssItem, RssChannel> for RssReader {
             links[0].clone()
         };
         let last_date = RssReader::extract_date_from_rss(entry, source);
+        let dated = entry.pub_date().is_some()
+            || entry.dublin_core_ext().is_some_and(|dc| !dc.dates().is_empty());
         let message = Message {
             authors,
             content,
             id,
             last_date: last_date?.naive_utc(),
+            dated,
             links,
             title: entry.title().unwrap_or("").to_owned(),
+            feed_title: source.title().to_owned(),
+            categories: entry.categories().iter().map(|c| c.name().to_owned()).collect(),
+            revision: None,
         };
         Ok(message)
     }
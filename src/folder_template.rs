@@ -0,0 +1,56 @@
+use tests_bin::unit_tests;
+
+/// Placeholders which can be used in folder names, resolved for each message
+pub const PLACEHOLDERS: [&str; 5] = ["{feed_title}", "{feed_host}", "{year}", "{month}", "{category}"];
+
+/// Values replacing placeholders of a folder name, for one message
+#[unit_tests("folder_template.rs")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderValues {
+    /// Title of feed, or feed host when feed has no title
+    pub feed_title: String,
+    /// Host of feed url
+    pub feed_host: String,
+    /// Year of message date, on four digits
+    pub year: String,
+    /// Month of message date, on two digits
+    pub month: String,
+    /// First category of message, or `Uncategorized` when message has none
+    pub category: String,
+}
+
+/// Check if the given folder contains placeholders (in which case it can only be known once messages are read)
+pub fn is_template(folder: &str) -> bool {
+    PLACEHOLDERS.iter().any(|placeholder| folder.contains(placeholder))
+}
+
+/// Replaces all placeholders of folder with the given values.
+/// Values can't create sub folders : hierarchy delimiters in them (slashes, and dots used by some servers and by Maildir)
+/// are replaced by dashes.
+pub fn resolve(folder: &str, values: &FolderValues) -> String {
+    let replacements = [
+        &values.feed_title,
+        &values.feed_host,
+        &values.year,
+        &values.month,
+        &values.category,
+    ];
+    let mut resolved = String::new();
+    let mut remaining = folder;
+    // Placeholders are replaced in a single pass, so values containing placeholders are kept as is
+    while let Some((position, index)) = PLACEHOLDERS.iter()
+        .enumerate()
+        .filter_map(|(index, placeholder)| remaining.find(placeholder).map(|position| (position, index)))
+        .min() {
+        resolved.push_str(&remaining[..position]);
+        resolved.push_str(&sanitize(replacements[index]));
+        remaining = &remaining[position + PLACEHOLDERS[index].len()..];
+    }
+    resolved.push_str(remaining);
+    resolved
+}
+
+/// Makes value usable as a single folder name element
+fn sanitize(value: &str) -> String {
+    value.trim().replace(['/', '\\', '.'], "-")
+}
@@ -0,0 +1,123 @@
+use chrono::NaiveDateTime;
+use custom_error::custom_error;
+use imap::types::Uid;
+use std::collections::BTreeMap;
+use tests_bin::unit_tests;
+
+use super::entry_state::EntryState;
+use super::feed::Feed;
+use super::session_pool::SessionPool;
+
+/// Header identifying the message containing feeds state
+pub const STATE_HEADER: &str = "X-RSS2IMAP-State";
+
+/// Number of times state is written again when another machine wrote it meanwhile
+const WRITE_ATTEMPTS: usize = 3;
+
+custom_error!{pub UnusableState
+    Imap{source:imap::error::Error} = "IMAP server error",
+    JsonParseError{source:serde_json::Error} = "Can't parse JSON content of state message",
+    NoBody = "State message has no body",
+    Conflict = "State has been written by another rrss2imap meanwhile"
+}
+
+/// Read state of one feed
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FeedState {
+    pub last_updated: NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_message: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub entries: BTreeMap<String, EntryState>,
+}
+
+/// Read state of all feeds (by feed url), as stored in a message of the IMAP state folder.
+/// This allows several machines to run rrss2imap on the same feeds without writing the same entries twice.
+#[unit_tests("imap_state.rs")]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct State {
+    pub feeds: BTreeMap<String, FeedState>,
+}
+
+impl State {
+    /// IMAP search query matching state messages
+    pub fn query() -> String {
+        format!("HEADER {} \"\"", STATE_HEADER)
+    }
+
+    /// State of the given feeds
+    pub fn of(feeds: &[Feed]) -> State {
+        State {
+            feeds: feeds.iter()
+                .map(|feed| (feed.url.clone(), FeedState {
+                    last_updated: feed.last_updated,
+                    last_message: feed.last_message.clone(),
+                    entries: feed.entries.clone(),
+                }))
+                .collect(),
+        }
+    }
+
+    /// Sets the state of the given feeds to the one stored here (feeds unknown here are left unchanged)
+    pub fn apply(&self, feeds: &mut [Feed]) {
+        for feed in feeds.iter_mut() {
+            if let Some(state) = self.feeds.get(&feed.url) {
+                feed.last_updated = state.last_updated;
+                feed.last_message = state.last_message.clone();
+                feed.entries = state.entries.clone();
+            }
+        }
+    }
+
+    /// Adds the state written by another machine. For each feed, the most recently read state is kept.
+    pub fn merge(&mut self, other: &State) {
+        for (url, state) in other.feeds.iter() {
+            match self.feeds.get(url) {
+                Some(known) if known.last_updated >= state.last_updated => {},
+                _ => {
+                    self.feeds.insert(url.clone(), state.clone());
+                },
+            }
+        }
+    }
+
+    /// Builds the message storing this state
+    pub fn to_message(&self) -> Result<String, UnusableState> {
+        Ok(format!(
+            "From: rrss2imap <rrss2imap@localhost>\r\nSubject: rrss2imap state\r\n{}: 1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{}\r\n",
+            STATE_HEADER,
+            serde_json::to_string_pretty(self)?.replace('\n', "\r\n")
+        ))
+    }
+
+    /// Reads state from the body of a state message
+    pub fn from_body(body: &[u8]) -> Result<State, UnusableState> {
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+/// State read from IMAP, and uid of the message it was read from (none when there was no state yet)
+pub struct StoredState {
+    pub state: State,
+    pub uid: Option<Uid>,
+}
+
+/// Writes the state of the given feeds in the state folder, replacing the state message having the expected uid.
+/// When another machine wrote state meanwhile, both states are merged (and feeds updated accordingly) before trying again.
+pub fn write_feeds_state(pool: &SessionPool, folder: &str, feeds: &mut [Feed], expected: Option<Uid>) -> Result<(), UnusableState> {
+    let mut state = State::of(feeds);
+    let mut expected = expected;
+    for _ in 0..WRITE_ATTEMPTS {
+        match pool.write_state(folder, &state, expected) {
+            Err(UnusableState::Conflict) => {
+                warn!("Feeds state in {} has been written by another rrss2imap meanwhile. Merging it", folder);
+                let stored = pool.read_state(folder)?;
+                state.merge(&stored.state);
+                state.apply(feeds);
+                expected = stored.uid;
+            },
+            result => return result,
+        }
+    }
+    Err(UnusableState::Conflict)
+}
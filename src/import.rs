@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use std::fs::File;
@@ -9,15 +10,22 @@ use super::store::Store;
 
 use treexml::*;
 
-pub fn import(from_file: &PathBuf, to_store: &mut Store) {
-    let mut file =
-        File::open(from_file).unwrap_or_else(|_| panic!("Unable to open file {:?}", from_file));
+use custom_error::custom_error;
+
+custom_error!{pub UnreadableOpml
+    IO{source:std::io::Error} = "input/output error",
+    XmlParseError{reason:String} = "Can't parse XML content of OPML file due to {reason}",
+    NoRootElement = "OPML file has no root element"
+}
+
+pub fn import(from_file: &PathBuf, to_store: &mut Store) -> Result<(), UnreadableOpml> {
+    let mut file = File::open(from_file)?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .unwrap_or_else(|_| panic!("Unable to read file {:?}", from_file));
+    file.read_to_string(&mut contents)?;
 
-    let doc = Document::parse(contents.as_bytes()).unwrap();
-    let root = doc.root.unwrap();
+    let doc = Document::parse(contents.as_bytes())
+        .map_err(|e| UnreadableOpml::XmlParseError { reason: format!("{:?}", e) })?;
+    let root = doc.root.ok_or(UnreadableOpml::NoRootElement)?;
 
     // old style parsing is good, because it is old :-)
     for element in root.children {
@@ -27,6 +35,7 @@ pub fn import(from_file: &PathBuf, to_store: &mut Store) {
             _ => error!("element {:?} was unexpected, please fill a bug !", element),
         }
     }
+    Ok(())
 }
 
 fn import_body(body: Element, to_store: &mut Store, folder: &str) {
@@ -53,9 +62,16 @@ fn import_outline(outline: Element, to_store: &mut Store, folder: &str) {
                     folder: Some(folder.to_string()),
                     from: None,
                     inline_image_as_data: false,
+                    flags: None,
+                    delivery: None,
+                    retention: None,
+                    account: None,
+                    track_updates: false,
+                    mark_updates: false,
                 },
                 last_updated: Feed::at_epoch(),
                 last_message: None,
+                entries: BTreeMap::new(),
             };
             to_store.add_feed(feed);
         } else {
@@ -0,0 +1,112 @@
+use chrono::Utc;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tests_bin::unit_tests;
+
+use super::delivery::*;
+
+/// Used to have distinct file names for messages written at the same time by different threads
+static DELIVERED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes messages in a local Maildir++ directory.
+/// Feed folder is used as Maildir++ subfolder (where both `/` and `.` are hierarchy delimiters),
+/// and an empty folder (or `INBOX`) designates the top-level Maildir.
+#[unit_tests("maildir.rs")]
+pub struct MaildirDelivery {
+    root: PathBuf,
+}
+
+impl MaildirDelivery {
+    pub fn new(root: &Path) -> MaildirDelivery {
+        MaildirDelivery { root: expand_home(root) }
+    }
+
+    /// Get the Maildir in which messages of the given folder are written
+    pub fn folder_path(&self, folder: &str) -> PathBuf {
+        let parts: Vec<&str> = folder
+            .split(['/', '.'])
+            .filter(|part| !part.is_empty())
+            .collect();
+        if parts.is_empty() || (parts.len() == 1 && parts[0].eq_ignore_ascii_case("INBOX")) {
+            self.root.clone()
+        } else {
+            self.root.join(format!(".{}", parts.join(".")))
+        }
+    }
+
+    /// Converts IMAP flags into the Maildir info suffix (like `:2,FS`).
+    /// Keywords have no Maildir equivalent, and are ignored.
+    pub fn info(flags: &[String]) -> Option<String> {
+        let mut letters: Vec<char> = flags
+            .iter()
+            .filter_map(|flag| match flag.as_str() {
+                "\\Draft" => Some('D'),
+                "\\Flagged" => Some('F'),
+                "\\Answered" => Some('R'),
+                "\\Seen" => Some('S'),
+                "\\Deleted" => Some('T'),
+                _ => {
+                    debug!("flag {} can't be set in Maildir", flag);
+                    None
+                }
+            })
+            .collect();
+        if letters.is_empty() {
+            None
+        } else {
+            letters.sort_unstable();
+            letters.dedup();
+            Some(format!(":2,{}", letters.into_iter().collect::<String>()))
+        }
+    }
+
+    /// Creates the Maildir (and marks it as a Maildir++ subfolder when it is one)
+    fn create(&self, maildir: &Path) -> std::io::Result<()> {
+        for directory in ["tmp", "new", "cur"] {
+            fs::create_dir_all(maildir.join(directory))?;
+        }
+        if maildir != self.root {
+            fs::write(maildir.join("maildirfolder"), "")?;
+        }
+        Ok(())
+    }
+
+    /// Unique file name, as advised by Maildir specification
+    fn unique_name() -> String {
+        let now = Utc::now();
+        let host = env::var("HOSTNAME")
+            .unwrap_or_else(|_| "localhost".to_owned())
+            .replace('/', "\\057")
+            .replace(':', "\\072");
+        format!(
+            "{}.M{}P{}Q{}.{}",
+            now.timestamp(),
+            now.timestamp_subsec_micros(),
+            process::id(),
+            DELIVERED_COUNT.fetch_add(1, Ordering::SeqCst),
+            host
+        )
+    }
+}
+
+impl Delivery for MaildirDelivery {
+    /// Writes message in `tmp/`, then moves it to `new/` (or to `cur/` when it has flags),
+    /// so mail readers never see partially written messages.
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage> {
+        let maildir = self.folder_path(envelope.folder);
+        self.create(&maildir)?;
+        let name = MaildirDelivery::unique_name();
+        let temporary = maildir.join("tmp").join(&name);
+        fs::write(&temporary, envelope.content)?;
+        let delivered = match MaildirDelivery::info(envelope.flags) {
+            Some(info) => maildir.join("cur").join(format!("{}{}", name, info)),
+            None => maildir.join("new").join(&name),
+        };
+        fs::rename(&temporary, &delivered)?;
+        debug!("Message written to {}", delivered.to_string_lossy());
+        Ok(())
+    }
+}
@@ -16,11 +16,14 @@
 //!         delete    Delete the given feed
 //!         email     Changes email address used in feed file to be the given one
 //!         export    Export subscriptions as opml file
+//!         flush     Write messages waiting in outbox
 //!         help      Prints this message or the help of the given subcommand(s)
 //!         import    import the given opml file into subscriptions
 //!         list      List all feeds configured
+//!         move      Move the given feed, and the messages already written for it, to another folder
 //!         new       Creates a new feedfile with the given email address
 //!         reset     Reset feedfile (in other words, remove everything)
+//!         resync    Rebuild read state of feeds from entries already written in IMAP
 //!         run       Run feed parsing and transformation
 //!
 //! Which give you a glimpse of what will happen
@@ -41,7 +44,7 @@
 //! * the used imap server
 //! ** with user login and password
 //! ** and security settings (secure should contain `{"Yes": secure port}` for
-//!    imap/s or `{"No": unsecure port}` for simple imap)
+//!    imap/s, `{"StartTls": port}` for imap upgraded with STARTTLS or `{"No": unsecure port}` for simple imap)
 //! * the default config
 //! ** folder will be the *full path to an imap folder* where entries will
 //!    fall in (e.g., `INBOX.News`).  The exact syntax depends on your email provider.
@@ -64,6 +67,12 @@
 //! 3. Transform these entries into valid email messages
 //! 4. Push these mail messages directly on IMAP server
 //!
+//! When IMAP server can't receive messages, they're put in an `outbox` directory next to `config.json`.
+//! They will be written at the beginning of next run (or when running `rrss2imap flush`).
+//! Queued files which can't be read (like ones truncated by a crash), and messages refused by server (like too big ones),
+//! are kept with a `.bad` extension, and skipped.
+//! Once server couldn't be reached, later messages of the run go directly to outbox.
+//!
 //! #### `rrss2imap list`
 //!
 //! Displays a list of the rss feeds. Here is an example
@@ -88,7 +97,7 @@
 //!           "user": "your imap user name",
 //!           "password": "your imap user password",
 //!           "secure": {
-//!             "Yes": 993 // Set to "Yes": port for imaps or "No": port for unsecure imap
+//!             "Yes": 993 // Set to "Yes": port for imaps, "StartTls": port for STARTTLS or "No": port for unsecure imap
 //!           }
 //!         },
 //!         // This config is to be used for all feeds
@@ -100,7 +109,9 @@
 //!             // Setting this to true will force rrss2imap to transform all images into
 //!             // base64. This prevents images from beind downloaded (and is really cool when reading feeds from a smartphone)
 //!             // But largely increase each mail size (which can be quite bothering)
-//!             "inline_image_as_data": true
+//!             "inline_image_as_data": true,
+//!             // IMAP flags and keywords set on written messages (like "\\Seen" to have them already read)
+//!             "flags": ["$Important"]
 //!         }
 //!       },
 //!       "feeds": [
@@ -114,6 +125,207 @@
 //!         },
 //! ```
 //!     
+//!
+//! #### OAuth2 authentication
+//!
+//! Some providers (like Gmail or Microsoft 365) no more accept password logins.
+//! For them, add an `auth` element to `email` settings
+//!
+//! ```json
+//!     "auth": {
+//!         // Either "XOAuth2" (the default) or "OAuthBearer"
+//!         "mechanism": "XOAuth2",
+//!         "token_endpoint": "https://oauth2.googleapis.com/token",
+//!         "client_id": "your OAuth2 client id",
+//!         "client_secret": "your OAuth2 client secret (if provider requires it)",
+//!         "refresh_token": "a refresh token obtained for that client"
+//!     }
+//! ```
+//!
+//! An access token is obtained from the token endpoint when rrss2imap first connects to the IMAP server, and reused
+//! by later connections until it expires. When the provider rotates refresh tokens (like Microsoft 365 does), the new one
+//! replaces the configured one in `config.json` (in `secrets` when the account has some, so it stays encrypted).
+//!
+//! #### Keeping password out of `config.json`
+//!
+//! Instead of the clear text `password`, `email` settings can define one of
+//!
+//! * `password_command`, a command which first output line is the password (like `pass show imap`)
+//! * `password_file`, a file which first line is the password
+//! * `password_env`, an environment variable containing the password
+//!
+//! The password is only read when rrss2imap connects to the IMAP server.
+//!
+//! #### Encrypted secrets
+//!
+//! Credentials can also be stored encrypted (using [age](https://age-encryption.org)) in the `secrets` block of `email` settings.
+//! They're encrypted either with the passphrase given in `RRSS2IMAP_PASSPHRASE` environment variable,
+//! or with an age identity file (generated by `age-keygen`), which path is given as `identity_file` in `secrets` block.
+//!
+//! * `rrss2imap secret set password` reads the password from standard input, encrypts it (and removes the clear text `password`).
+//!   `refresh_token` and `client_secret` can also be stored this way for OAuth2.
+//! * `rrss2imap secret rotate` encrypts all secrets again with the passphrase given in `RRSS2IMAP_NEW_PASSPHRASE`
+//!   (or with the identity file given with `--identity`).
+//!
+//! Secrets are only decrypted by commands connecting to a server (`run` without `--dry-run`, `flush`, `prune`, `resync` and `move`), or changing secrets: other commands don't need the key.
+//!
+//! #### Delivering messages through SMTP
+//!
+//! Instead of being written in IMAP folders, messages can be sent through an SMTP server (to go through server-side filters, or when only SMTP access is available).
+//! To do so, add an `smtp` block in settings
+//!
+//! ```json
+//! "smtp": {
+//!     "server": "smtp.example.com",
+//!     "secure": { "StartTls": 587 },
+//!     "user": "me@example.com"
+//! }
+//! ```
+//!
+//! and set `"delivery": "Smtp"` in global config, or in the config of the feeds to send this way.
+//! `secure` can be `Yes` (implicit TLS, typically on port 465), `StartTls` (typically on port 587) or `No`.
+//! Password can be given as `password`, or stored encrypted using `rrss2imap secret set smtp_password`.
+//! Messages are sent to the config email address, with `user` (or `sender`, when set) as envelope sender.
+//!
+//! #### Writing messages in a local Maildir
+//!
+//! To use rrss2imap fully offline (with notmuch, mu, ...), messages can be written in a local Maildir
+//! by setting `"delivery": { "Maildir": "~/Mail" }` in global config, or in the config of some feeds.
+//! Feed folder is then used as Maildir++ subfolder (`News/Comics` is written in `~/Mail/.News.Comics`),
+//! and messages are written in `new/` (or in `cur/`, with flags encoded in file name, when `flags` contains standard flags like `\Seen`).
+//!
+//! #### Archiving messages in mbox files
+//!
+//! Messages can also be appended to mbox files by setting `"delivery": { "Mbox": "~/Mail/archive" }`.
+//! Each folder then has its own `~/Mail/archive/<folder>.mbox` file (messages without folder go in `INBOX.mbox`).
+//! Files use the mboxrd format, and are locked with `<folder>.mbox.lock` files while written.
+//!
+//! #### Checking what would be delivered
+//!
+//! `rrss2imap run --dry-run --output-dir DIR` reads feeds, and writes every new entry as a `DIR/<folder>/<id>.eml` file
+//! (with a `DIR/index.html` file listing them all) instead of delivering it.
+//! Neither IMAP server nor config file are modified, so the same entries will be read again on next run.
+//!
+//! #### Removing old messages
+//!
+//! A `retention` can be set in global config, or in the config of some feeds, to remove old messages from IMAP folders
+//!
+//! ```json
+//! "retention": {
+//!     "max_age_days": 30,
+//!     "max_count": 500,
+//!     "trash": "Trash"
+//! }
+//! ```
+//!
+//! Messages older than `max_age_days` are removed, as well as the oldest ones when there are more than `max_count` messages.
+//! When `trash` is set, messages are moved to that folder instead of being deleted.
+//! Only messages written by rrss2imap (which have an `X-RSS-Entry-Id` header) are removed, and flagged messages are always kept.
+//! Messages are removed at the end of each run, and by the `prune` subcommand.
+//! On servers without IMAP `MOVE`, messages are copied to `trash`, then deleted. On servers without UIDPLUS
+//! (which allows removing only some deleted messages), all messages flagged as deleted in the folder are removed.
+//!
+//! #### Custom TLS settings
+//!
+//! When IMAP server uses a certificate signed by a private CA, or requires a client certificate, `email` settings accept
+//!
+//! * `ca_file`, a PEM file containing the certificate of the CA to trust
+//! * `client_cert` and `client_key`, PEM files containing the client certificate and its PKCS#8 private key
+//! * `min_tls_version`, one of `Tls10`, `Tls11` or `Tls12`
+//! * `danger_accept_invalid_certs`, which disables all certificate checks (to use only with a self-signed certificate on a trusted network)
+//!
+//! These settings only apply to the IMAP connection: feeds are still read with the system trusted CAs.
+//!
+//! #### Multiple IMAP accounts
+//!
+//! Besides the default `email` account, other IMAP accounts can be defined in settings
+//!
+//! ```json
+//! "accounts": {
+//!     "alice": {
+//!         "server": "imap.example.com",
+//!         "user": "alice@example.com",
+//!         "password": "..."
+//!     }
+//! }
+//! ```
+//!
+//! They accept the same settings as `email`. A feed is then written in one of them by setting `"account": "alice"` in its config
+//! (or in global config, in which case feeds without account use it). Feeds without account use the default `email` account.
+//! Secrets of an account are set with `rrss2imap secret set --account alice password`.
+//!
+//! #### Exit codes
+//!
+//! rrss2imap exits with a non-zero code when something went wrong, so cron (or any scheduler) can notice it.
+//!
+//! * `2` when `config.json` can't be read or written, an OPML file can't be imported or exported, the given feed or
+//!   account doesn't exist, or secrets can't be encrypted
+//! * `3` when an IMAP server can't be reached (including when `flush` or `prune` can't write to it, or when messages had to be
+//!   put in outbox), or no feed could be read
+//! * `4` when only some feeds couldn't be read, or some messages couldn't be delivered (these are read again on next run),
+//!   or were refused by IMAP server
+//!
+//! At the end of `rrss2imap run`, feeds which couldn't be read and messages which couldn't be delivered are listed.
+//!
+//! #### Folder templates
+//!
+//! `folder` (at feed or global level) can contain placeholders replaced for each message:
+//!
+//! * `{feed_title}` title of the feed (its host when it has no title)
+//! * `{feed_host}` host of the feed url
+//! * `{year}` and `{month}` date of the message
+//! * `{category}` first category of the message (`Uncategorized` when it has none)
+//!
+//! So a global `"folder": "RSS/{feed_title}"` gives every feed its own folder. Missing folders are created when first written to.
+//! Slashes, backslashes and dots in values are replaced by dashes, so values never create sub folders (whatever the server
+//! hierarchy delimiter is). Retention is not applied to folders built from templates.
+//!
+//! #### Updated entries
+//!
+//! Entries are often corrected after publication. When `"track_updates": true` is set (at feed or global level),
+//! rrss2imap remembers each written entry (content hash, date and IMAP UID) in `config.json`,
+//! and writes an entry again when its content changed, or when its own date moved forward (RSS items without date
+//! only use their content). The previous version is then removed from IMAP
+//! (provided the server supports `UID EXPUNGE`, from UIDPLUS extension). Setting `"mark_updates": true` prefixes
+//! the subject of these new versions with `[updated]`.
+//!
+//! The IMAP library used (imap 2.4) doesn't expose the `APPENDUID` response, so UIDs are found by searching the written message
+//! Message-ID (only on servers supporting UIDPLUS, as the others can't remove messages by UID). When a new version can't be
+//! found (like when it was put in outbox), the previous version is kept, and removed by the next update.
+//!
+//! #### Rebuilding feeds state
+//!
+//! When `config.json` is lost, or restored from an old backup, feeds would be read again from the start, flooding the mailbox.
+//! `rrss2imap resync` reads every feed written to IMAP, searches its entries (using their `X-RSS-Entry-Id` header) in the folder
+//! they would be written to, and makes the most recent one found the last read entry of the feed
+//! (unless feed state is already more recent).
+//!
+//! #### Sharing feeds state between machines
+//!
+//! When rrss2imap runs alternately from several machines, each one has its own `config.json`, and entries get written twice.
+//! Setting `"state_folder": "rrss2imap.state"` in `settings` keeps the read state of all feeds in a message of that IMAP folder
+//! (of the default account). This state is read at the beginning of each run, and replaced at its end.
+//! When another machine replaced it meanwhile (which is detected using the state message UID), both states are merged,
+//! keeping the most recently read state of each feed. If the state can't be read, feeds are not read at all.
+//! Dry runs don't read it (as they never connect to IMAP), and use the state of the local `config.json`.
+//!
+//! #### Moving a feed to another folder
+//!
+//! `rrss2imap move <feed> <folder>` changes the folder of the given feed (as numbered by `rrss2imap list`), and moves the
+//! messages already written for it from the previous folder to the new one (using IMAP `MOVE`, or `COPY` when server
+//! doesn't support it). Messages are identified by their `X-RSS-Feed-Url` header (which must be exactly the feed url).
+//! That header is only written since `move` exists : older messages of the feed stay in the previous folder, and must be
+//! moved by hand.
+//!
+//! #### IMAP quota
+//!
+//! When the IMAP server supports the `QUOTA` extension, setting `"quota": {"warn": 80, "no_inline": 90, "stop": 98}` in `settings`
+//! makes each run read storage usage of every IMAP account feeds are written to (using `GETQUOTAROOT` on `INBOX`), and
+//! * log a warning when usage is above `warn` percents (80 by default),
+//! * stop inlining images (whatever `inline_image_as_data` is) when usage is above `no_inline` percents,
+//! * skip feeds written to that account when usage is above `stop` percents. Those feeds are reported as unreadable, so the run ends with the partial failure exit code.
+//!
+//! Servers without quota are used as usual, and dry runs don't check quota.
 
 extern crate structopt;
 #[macro_use]
@@ -143,20 +355,38 @@ extern crate custom_error;
 use flexi_logger::Logger;
 use std::path::PathBuf;
 use structopt::StructOpt;
-use std::error::Error;
+use std::process::ExitCode;
 
 mod config;
+mod delivery;
+mod dry_run;
+mod entry_state;
 mod export;
 mod feed_errors;
 mod feed_reader;
 mod feed_utils;
 mod feed;
+mod folder_template;
 mod image_to_data;
+mod imap_state;
 mod import;
+mod maildir;
+mod mbox;
 mod message;
+mod oauth;
+mod retention;
+mod outbox;
+mod quota;
+mod secrets;
+mod session_pool;
 mod settings;
+mod smtp;
 mod store;
 mod syndication;
+mod utf7;
+
+// Fixtures shared by unit tests
+tests_bin::unit__tests!("fixtures.rs", "fixtures");
 
 ///
 /// rrss2imap is a script used to transform rss feed entries into mail messages that are directly dropped
@@ -185,7 +415,29 @@ enum Command {
     Email { email: String },
     /// Run feed parsing and transformation
     #[structopt(name = "run")]
-    Run,
+    Run {
+        /// Don't deliver messages nor save feeds state, but write messages as .eml files in output directory
+        #[structopt(long = "dry-run", requires = "output-dir")]
+        dry_run: bool,
+        /// Directory in which messages are written during a dry run
+        #[structopt(long = "output-dir", requires = "dry-run", parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+    },
+    /// Remove expired messages from folders of feeds having a retention
+    #[structopt(name = "prune")]
+    Prune,
+    /// Rebuild read state of feeds from the entries already written in their IMAP folders
+    #[structopt(name = "resync")]
+    Resync,
+    /// Write messages waiting in outbox (because IMAP server was unavailable during a previous run)
+    #[structopt(name = "flush")]
+    Flush,
+    /// Manage secrets stored encrypted in feed file
+    #[structopt(name = "secret")]
+    Secret {
+        #[structopt(subcommand)]
+        action: SecretAction,
+    },
     /// Adds a new feed given its url.
     /// This option can use either named parameters or positional parameters.
     /// Although positional parameters may seems simpler to use, they're of a more weird usage
@@ -230,6 +482,16 @@ enum Command {
         // index of the feed to delete
         feed: u32,
     },
+    /// Move the given feed, and the messages already written for it, to another folder
+    ///
+    /// Only messages having the `X-RSS-Feed-Url` header (written by versions of rrss2imap having this command) are moved.
+    #[structopt(name = "move")]
+    Move {
+        // index of the feed to move
+        feed: u32,
+        /// folder messages of the feed are moved to
+        folder: String,
+    },
     /// Export subscriptions as opml file
     #[structopt(name = "export")]
     Export {
@@ -246,8 +508,75 @@ enum Command {
     },
 }
 
+#[derive(Debug, StructOpt)]
+enum SecretAction {
+    /// Encrypts the given secret (`password`, `refresh_token`, `client_secret` or `smtp_password`) in feed file
+    #[structopt(name = "set")]
+    Set {
+        /// account (defined in `accounts` settings) secret belongs to, default account if not present
+        #[structopt(short = "a", long = "account")]
+        account: Option<String>,
+        /// name of the secret
+        name: String,
+        /// value of the secret, read from standard input if not present
+        value: Option<String>,
+    },
+    /// Encrypts all secrets again, with the new passphrase given in RRSS2IMAP_NEW_PASSPHRASE
+    /// environment variable, or the given identity file
+    #[structopt(name = "rotate")]
+    Rotate {
+        /// age identity file to use from now on
+        #[structopt(short = "i", long = "identity", parse(from_os_str))]
+        identity: Option<PathBuf>,
+    },
+}
+
+/// Process exit code when config file can't be read or written (or an OPML file can't be imported)
+pub const CONFIG_ERROR: u8 = 2;
+/// Process exit code when an IMAP server can't be reached, or no feed can be read
+pub const NETWORK_ERROR: u8 = 3;
+/// Process exit code when some feeds can't be read, or some messages can't be delivered
+pub const PARTIAL_FAILURE: u8 = 4;
+
+/// Converts the result of a store operation into a process exit code
+fn exit_code(result: Result<(), store::UnusableStore>) -> u8 {
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{}", e);
+            CONFIG_ERROR
+        }
+    }
+}
+
+/// Converts the result of a command writing to IMAP into a process exit code
+fn imap_exit_code(result: Result<(), store::FailedImapCommand>) -> u8 {
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{}", e);
+            match e {
+                store::FailedImapCommand::NotFlushed { .. } | store::FailedImapCommand::NotPruned { .. } => NETWORK_ERROR,
+                _ => CONFIG_ERROR,
+            }
+        }
+    }
+}
+
+/// Converts the summary of a run into a process exit code
+fn run_exit_code(summary: store::RunSummary) -> u8 {
+    summary.log();
+    if summary.is_success() {
+        0
+    } else if summary.is_network_failure() {
+        NETWORK_ERROR
+    } else {
+        PARTIAL_FAILURE
+    }
+}
+
 /// Main function simply load the RRSS2IMAP struct from the command-line arguments
-pub fn main() -> Result<(), Box<dyn Error>> {
+pub fn main() -> ExitCode {
     if !cfg!(debug_assertions) {
         setup_panic!();
     }
@@ -271,29 +600,63 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 
     let store_path = store::find_store();
     let store_result = store::Store::load(&store_path);
-    match store_result {
+    let code = match store_result {
         Ok(mut store) => {
-            match opt.cmd {
-                Command::New { email } => store.init_config(email),
-                Command::Email { email } => store.set_email(email),
+            // Secrets are only decrypted for commands connecting to a server
+            let unlocked = match opt.cmd {
+                // Dry runs never connect
+                Command::Run { dry_run: true, output_dir: Some(_) } => Ok(()),
+                Command::Move { .. } | Command::Run { .. } | Command::Flush | Command::Prune | Command::Resync => store.unlock_secrets(),
+                _ => Ok(()),
+            };
+            let code = match unlocked {
+                Err(e) => exit_code(Err(e)),
+                Ok(_) => match opt.cmd {
+                    Command::New { email } => exit_code(store.init_config(email)),
+                    Command::Email { email } => exit_code(store.set_email(email)),
 
-                Command::List => store.list(),
+                    Command::List => { store.list(); 0 },
 
-                Command::Add { url, email, destination, inline_images, do_not_inline_images, parameters } =>
-                    store.add(url, email, destination, store.settings.config.inline(inline_images, do_not_inline_images), parameters),
-                Command::Delete { feed } => store.delete(feed),
+                    Command::Add { url, email, destination, inline_images, do_not_inline_images, parameters } => {
+                        store.add(url, email, destination, store.settings.config.inline(inline_images, do_not_inline_images), parameters);
+                        0
+                    },
+                    Command::Delete { feed } => exit_code(store.delete(feed)),
+                    Command::Move { feed, folder } => match store.move_feed(feed, folder) {
+                        Ok(_) => 0,
+                        Err(e) => {
+                            error!("{}", e);
+                            match e {
+                                store::UnmovableFeed::Imap { .. } => NETWORK_ERROR,
+                                _ => CONFIG_ERROR,
+                            }
+                        }
+                    },
 
-                Command::Reset => store.reset(),
+                    Command::Reset => { store.reset(); 0 },
 
-                Command::Run => store.run(),
+                    Command::Run { dry_run, output_dir } => run_exit_code(store.run(output_dir.filter(|_| dry_run))),
+                    Command::Flush => imap_exit_code(store.flush()),
+                    Command::Prune => imap_exit_code(store.prune()),
+                    Command::Resync => run_exit_code(store.resync()),
 
-                Command::Export { output } => store.export(output),
-                Command::Import { input } => store.import(input),
+                    Command::Secret { action: SecretAction::Set { account, name, value } } => exit_code(store.set_secret(account, name, value)),
+                    Command::Secret { action: SecretAction::Rotate { identity } } => exit_code(store.rotate_secrets(identity)),
+
+                    Command::Export { output } => exit_code(store.export(output)),
+                    Command::Import { input } => exit_code(store.import(input)),
+                },
+            };
+            // A failed save is worse than any other failure, as it means next run will read everything again
+            match store.close() {
+                Ok(_) => code,
+                Err(e) => exit_code(Err(e)),
             }
         },
         Err(e) => {
             error!("Impossible to open store {}\n{}", store_path.to_string_lossy(), e);
+            CONFIG_ERROR
         }
-    }
-    Ok(())
+    };
+    ExitCode::from(code)
 }
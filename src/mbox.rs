@@ -0,0 +1,106 @@
+use chrono::Utc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{thread, time};
+use tests_bin::unit_tests;
+
+use super::delivery::*;
+
+/// How many times we try to get the lock on a mbox file before giving up
+const LOCK_ATTEMPTS: u32 = 50;
+/// Lock files older than that are considered as left by a crashed program, and removed
+const STALE_LOCK: time::Duration = time::Duration::from_secs(300);
+
+/// Appends messages to mbox files (one per folder) stored in a directory.
+/// Messages are written in mboxrd format (lines starting with `From ` are escaped by adding a leading `>`).
+/// Files are locked using `.lock` files, as done by most mail programs.
+#[unit_tests("mbox.rs")]
+pub struct MboxDelivery {
+    root: PathBuf,
+    /// As dot locking is not reentrant, threads of this process first have to get this lock
+    writing: Mutex<()>,
+}
+
+impl MboxDelivery {
+    pub fn new(root: &Path) -> MboxDelivery {
+        MboxDelivery {
+            root: expand_home(root),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Get the mbox file in which messages of the given folder are written
+    pub fn file_path(&self, folder: &str) -> PathBuf {
+        let folder = folder.trim_matches('/');
+        let folder = if folder.is_empty() { "INBOX" } else { folder };
+        self.root.join(format!("{}.mbox", folder))
+    }
+
+    /// Converts message to mbox format : lines are LF terminated, `From ` lines are escaped,
+    /// a `From ` separator line is added before, and an empty line after message.
+    pub fn format(envelope: &Envelope) -> String {
+        let date = envelope.date.unwrap_or_else(|| Utc::now().naive_utc());
+        let mut text = format!("From rrss2imap {}\n", date.format("%a %b %e %H:%M:%S %Y"));
+        for line in envelope.content.lines() {
+            if line.trim_start_matches('>').starts_with("From ") {
+                text.push('>');
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push('\n');
+        text
+    }
+
+    /// Creates the lock file associated to mbox, waiting for other programs to release it
+    fn lock(file: &Path) -> io::Result<PathBuf> {
+        let mut lock = file.as_os_str().to_owned();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        for _ in 0..LOCK_ATTEMPTS {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(lock),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&lock)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| modified.elapsed().unwrap_or_default() > STALE_LOCK)
+                        .unwrap_or(false);
+                    if stale {
+                        warn!("Removing stale lock file {}", lock.to_string_lossy());
+                        let _ = fs::remove_file(&lock);
+                    } else {
+                        thread::sleep(time::Duration::from_millis(100));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} is locked by another program", file.to_string_lossy()),
+        ))
+    }
+}
+
+impl Delivery for MboxDelivery {
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage> {
+        let file = self.file_path(envelope.folder);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = MboxDelivery::format(envelope);
+        let _writing = self.writing.lock().unwrap();
+        let lock = MboxDelivery::lock(&file)?;
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .and_then(|mut mbox: File| mbox.write_all(text.as_bytes()));
+        fs::remove_file(&lock)?;
+        written?;
+        debug!("Message written to {}", file.to_string_lossy());
+        Ok(())
+    }
+}
@@ -1,21 +1,37 @@
-use chrono::NaiveDateTime;
+use chrono::{Datelike, NaiveDateTime, Utc};
 
+use super::delivery::*;
 use super::feed::Feed;
+use super::folder_template::{self, FolderValues};
 use super::image_to_data;
+use super::retention::{self, ENTRY_HEADER, FEED_HEADER};
 use super::settings::*;
+use mail_builder::headers::raw::Raw;
 use mail_builder::MessageBuilder;
 use custom_error::custom_error;
+use sha2::{Digest, Sha256};
+use tests_bin::unit_tests;
 
 custom_error!{pub UnprocessableMessage
     CantPutDateInMessage{ value:String } = "EmailMessage can't parse date from {value}",
     CantPutFirstAuthorInMessage { value:String } = "Unable to parse first author {value}.
     Please consider adding in feed config the \"from\": ... field",
-    CantWriteTransformedMessage = "Can't re-write transformed message after image Base64'ing"
+    CantWriteTransformedMessage = "Can't re-write transformed message after image Base64'ing",
+    CantDeliverMessage { source: UndeliverableMessage } = "Can't deliver message"
+}
+
+impl UnprocessableMessage {
+    /// Check if error may not happen on next run (like failed deliveries or image inlining),
+    /// in which case message must be tried again instead of being considered as written
+    pub fn is_temporary(&self) -> bool {
+        matches!(self, UnprocessableMessage::CantWriteTransformedMessage | UnprocessableMessage::CantDeliverMessage { .. })
+    }
 }
 
 ///
 /// Structure for storing message data prior to having these messages written to IMAP.
 /// This structure serves as a common interface for Item/Entry
+#[unit_tests("message.rs")]
 #[derive(Clone)]
 pub struct Message {
     /// List of message authors
@@ -25,28 +41,68 @@ pub struct Message {
     /// Message id
     pub id: String,
     pub last_date: NaiveDateTime,
+    /// Whether last_date is the date of entry itself (and not the one of its feed, for RSS items without date)
+    pub dated: bool,
     pub links: Vec<String>,
     pub title: String,
+    /// Title of the feed message comes from
+    pub feed_title: String,
+    /// Categories (or tags) of message
+    pub categories: Vec<String>,
+    /// When set, this message is a new version of an already written entry, with that content hash and date
+    pub revision: Option<String>,
 }
 
 impl Message {
-    pub fn write_to_imap(&self, feed: &Feed, settings: &Settings) {
-        let folder = feed.config.get_folder(&settings.config);
+    /// Delivers message to the feed folder (or email address), using the delivery configured for feed.
+    /// When feed tracks updates, the location of written message is returned (if delivery can find it).
+    /// Returns a `CantDeliverMessage` error when delivery failed,
+    /// and other errors when message couldn't even be built.
+    pub fn deliver(&self, feed: &Feed, settings: &Settings, deliveries: &Deliveries) -> Result<Option<Location>, UnprocessableMessage> {
+        let folder = self.folder(feed, settings);
+        let delivery = deliveries.get(&feed.config.get_delivery(&settings.config), &feed.config.get_account(&settings.config))?;
+        let message_id = self.message_id(feed);
+        match delivery.contains(&folder, &message_id) {
+            Ok(true) => {
+                info!("Item titled {} already exists in {}. It won't be written again", self.title, folder);
+                return Ok(None);
+            },
+            Ok(false) => {},
+            Err(e) => warn!("{}\nUnable to search mailbox {} for item titled {}", e, folder, self.title),
+        }
         let content = self.build_message(feed, settings);
         match content {
             Ok(text) => {
-                debug!("===========================\nWriting message content to IMAP\n{}\n===========================", 
+                debug!("===========================\nWriting message content\n{}\n===========================", 
                     text);
-                match settings.email.append(&folder, &text) {
-                    Ok(_) => debug!("Successfully written {}", self.title),
-                    Err(e) => error!(
-                        "{}\nUnable to select mailbox {}. Item titled {} won't be written",
-                        e, &folder, self.title
-                    ),
+                let envelope = Envelope {
+                    message_id: &message_id,
+                    title: &self.title,
+                    folder: &folder,
+                    recipient: &self.recipient(feed, settings),
+                    content: &text,
+                    flags: &feed.config.get_flags(&settings.config),
+                    date: self.internal_date(),
+                };
+                match delivery.deliver(&envelope) {
+                    Ok(_) => {
+                        debug!("Successfully written {}", self.title);
+                        if !feed.config.get_track_updates(&settings.config) {
+                            return Ok(None);
+                        }
+                        Ok(delivery.locate(&envelope)
+                            .inspect_err(|e| warn!("{}\nUnable to find written item titled {}", e, self.title))
+                            .unwrap_or(None))
+                    },
+                    Err(e) => {
+                        error!("{}\nUnable to deliver item titled {}. It will be read again on next run", e, self.title);
+                        Err(UnprocessableMessage::CantDeliverMessage { source: e })
+                    },
                 }
             },
             Err(error) => {
                 warn!("Couldn(t write message {:?} from feed {} due to {}", self.links, feed.url, error);
+                Err(error)
             }
         }
     }
@@ -64,13 +120,85 @@ impl Message {
         }
     }
 
-    fn build_message(&self, feed: &Feed, settings: &Settings) -> Result<String, UnprocessableMessage> {
+    /// Email address message is sent to : the config one, or the user of the account feed is written to
+    fn recipient(&self, feed: &Feed, settings: &Settings) -> String {
+        let email = feed.config.get_email(&settings.config);
+        if email.is_empty() {
+            settings.account(&feed.config.get_account(&settings.config))
+                .unwrap_or(&settings.email)
+                .user
+                .to_owned()
+        } else {
+            email
+        }
+    }
+
+    /// Folder message is written to : the feed (or default) one, with placeholders replaced by message values
+    pub fn folder(&self, feed: &Feed, settings: &Settings) -> String {
+        let folder = feed.config.get_folder(&settings.config);
+        if !folder_template::is_template(&folder) {
+            return folder;
+        }
+        let feed_host = url::Url::parse(&feed.url).ok()
+            .and_then(|url| url.host_str().map(|host| host.to_owned()))
+            .unwrap_or_default();
+        let date = self.internal_date().unwrap_or_else(|| Utc::now().naive_utc());
+        folder_template::resolve(&folder, &FolderValues {
+            feed_title: if self.feed_title.trim().is_empty() { feed_host.clone() } else { self.feed_title.clone() },
+            feed_host,
+            year: format!("{:04}", date.year()),
+            month: format!("{:02}", date.month()),
+            category: self.categories.first().cloned().unwrap_or_else(|| "Uncategorized".to_owned()),
+        })
+    }
+
+    /// Date used as IMAP internal date. Messages without date (which have epoch as date) use delivery date.
+    pub fn internal_date(&self) -> Option<NaiveDateTime> {
+        if self.last_date == Feed::at_epoch() {
+            None
+        } else {
+            Some(self.last_date)
+        }
+    }
+
+    /// Computes a Message-ID which only depends upon feed url and entry id.
+    /// This way, the same entry will always produce the same Message-ID, allowing duplicates detection.
+    /// New versions of an entry (which have a revision) get their own Message-ID.
+    pub fn message_id(&self, feed: &Feed) -> String {
+        let mut parts = vec![feed.url.as_str(), self.id.as_str()];
+        if let Some(revision) = &self.revision {
+            parts.push(revision);
+        }
+        format!("{}@rrss2imap", Message::hash(&parts))
+    }
+
+    /// Hash of message title and content, used to detect changed entries
+    pub fn content_hash(&self) -> String {
+        Message::hash(&[self.title.as_str(), self.content.as_str()])
+    }
+
+    /// Hexadecimal SHA-256 (truncated to 16 bytes) of the given parts, separated by new lines
+    fn hash(parts: &[&str]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(parts.join("\n").as_bytes());
+        hasher.finalize()
+            .iter()
+            .take(16)
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    pub fn build_message(&self, feed: &Feed, settings: &Settings) -> Result<String, UnprocessableMessage> {
         let content = self.extract_content(feed, settings);
         debug!("===========================\nCreating message content\n{}\n===========================", content);
         let from = self.build_from(feed, settings);
         let _date = self.date_text();
-        let to_addr = settings.config.email.as_ref().unwrap_or(&settings.email.user);
+        let to_addr = self.recipient(feed, settings);
         let email = MessageBuilder::new()
+            .message_id(self.message_id(feed))
+            // Entry id comes from feed, and must not be able to add headers
+            .header(ENTRY_HEADER, Raw::new(retention::header_safe(&self.id)))
+            .header(FEED_HEADER, Raw::new(retention::header_safe(&feed.url)))
             .from(from)
             .to(to_addr.as_str())
             .subject(str::replace(self.title.as_str(), "\n", ""))
@@ -123,8 +251,8 @@ impl Message {
     /// This should allow
     /// * image transformation into base64 when needed
     ///
-    pub fn get_processed_content(html_content:&String, feed: &Feed, settings: &Settings) -> Result<String, UnprocessableMessage> {
-        if feed.config.inline_image_as_data || settings.config.inline_image_as_data {
+    pub fn get_processed_content(html_content:&String, inline: bool) -> Result<String, UnprocessableMessage> {
+        if inline {
             match image_to_data::transform(html_content) {
                 Ok(transformed_html_content) => Ok(transformed_html_content),
                 Err(_) => Err(UnprocessableMessage::CantWriteTransformedMessage)
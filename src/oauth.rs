@@ -0,0 +1,182 @@
+use custom_error::custom_error;
+use imap::Authenticator;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tests_bin::unit_tests;
+
+custom_error!{pub UnusableToken
+    Request{ endpoint:String, reason:String } = "Unable to get an access token from {endpoint}: {reason}",
+    IO{ source:std::io::Error } = "Unable to read token endpoint response",
+    JsonParseError{ source:serde_json::Error } = "Can't parse token endpoint response"
+}
+
+/// SASL mechanisms usable with an OAuth2 access token
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum Mechanism {
+    /// Google/Microsoft proprietary mechanism
+    XOAuth2,
+    /// Standard mechanism, as defined in RFC 7628
+    OAuthBearer,
+}
+
+/// Access tokens are considered expired that long before the end of their lifetime given by token endpoint
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(60);
+
+/// OAuth2 authentication config.
+/// Access tokens are obtained from the token endpoint using the refresh token, and reused by all connections
+/// to IMAP server until they expire.
+#[unit_tests("oauth.rs")]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct OAuth {
+    /// SASL mechanism used to send the access token to the IMAP server
+    #[serde(default = "OAuth::default_mechanism")]
+    pub mechanism: Mechanism,
+    /// url of the OAuth2 token endpoint (like `https://oauth2.googleapis.com/token`)
+    pub token_endpoint: String,
+    /// OAuth2 client id registered at provider
+    pub client_id: String,
+    /// OAuth2 client secret, for providers which require it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_secret: Option<String>,
+    /// Long lived token used to get access tokens (may instead be stored in email secrets)
+    #[serde(default)]
+    pub refresh_token: String,
+    /// Scope to request, for providers which require it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub scope: Option<String>,
+    /// Tokens obtained from token endpoint during this run (never written in config file)
+    #[serde(skip)]
+    pub tokens: TokenCache,
+}
+
+/// Tokens obtained from token endpoint, shared by all clones of an OAuth config
+#[derive(Debug, Clone, Default)]
+pub struct TokenCache(Arc<Mutex<Tokens>>);
+
+#[derive(Debug, Default)]
+struct Tokens {
+    /// Last access token, and the instant it expires (when endpoint told it)
+    access_token: Option<(String, Option<Instant>)>,
+    /// Refresh token sent by endpoint to replace the configured one (for providers rotating them)
+    refresh_token: Option<String>,
+}
+
+/// Obtained tokens are not part of config
+impl PartialEq for TokenCache {
+    fn eq(&self, _other: &TokenCache) -> bool {
+        true
+    }
+}
+
+/// Token endpoint response (see RFC 6749 section 5.1)
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    /// lifetime of access token, in seconds
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+impl OAuth {
+    /// default mechanism, used by serde
+    pub fn default_mechanism() -> Mechanism {
+        Mechanism::XOAuth2
+    }
+
+    /// SASL name of the configured mechanism
+    pub fn mechanism_name(&self) -> &'static str {
+        match self.mechanism {
+            Mechanism::XOAuth2 => "XOAUTH2",
+            Mechanism::OAuthBearer => "OAUTHBEARER",
+        }
+    }
+
+    /// Refresh token sent by token endpoint to replace the configured one, if any
+    pub fn rotated_refresh_token(&self) -> Option<String> {
+        self.tokens.0.lock().unwrap().refresh_token.clone()
+    }
+
+    /// Gets an access token, reusing the previous one while it is not expired.
+    /// Access tokens without a known lifetime are never reused.
+    pub fn access_token(&self) -> Result<String, UnusableToken> {
+        let mut tokens = self.tokens.0.lock().unwrap();
+        if let Some((token, Some(expiration))) = &tokens.access_token {
+            if Instant::now() + EXPIRATION_MARGIN < *expiration {
+                return Ok(token.clone());
+            }
+        }
+        // Once rotated, the configured refresh token may no more be valid
+        let refresh_token = tokens.refresh_token.clone().unwrap_or_else(|| self.refresh_token.clone());
+        let response = self.request_token(&refresh_token)?;
+        if let Some(rotated) = response.refresh_token.filter(|t| t != &refresh_token) {
+            info!("Token endpoint {} sent a new refresh token, which will replace the configured one", self.token_endpoint);
+            tokens.refresh_token = Some(rotated);
+        }
+        let expiration = response.expires_in.map(|seconds| Instant::now() + Duration::from_secs(seconds));
+        tokens.access_token = Some((response.access_token.clone(), expiration));
+        Ok(response.access_token)
+    }
+
+    /// Obtains a fresh access token using the given refresh token
+    fn request_token(&self, refresh_token: &str) -> Result<TokenResponse, UnusableToken> {
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope.as_str()));
+        }
+        debug!("Requesting access token from {}", self.token_endpoint);
+        let response = ureq::post(&self.token_endpoint)
+            .send_form(&form)
+            .map_err(|e| UnusableToken::Request {
+                endpoint: self.token_endpoint.clone(),
+                reason: e.to_string(),
+            })?;
+        Ok(serde_json::from_str(&response.into_string()?)?)
+    }
+
+    /// Creates the SASL authenticator for the given user and access token
+    pub fn authenticator(&self, user: &str, server: &str, port: u16, token: &str) -> SaslAuthenticator {
+        let response = match self.mechanism {
+            Mechanism::XOAuth2 => format!("user={}\x01auth=Bearer {}\x01\x01", user, token),
+            Mechanism::OAuthBearer => format!(
+                "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+                user, server, port, token
+            ),
+        };
+        SaslAuthenticator {
+            mechanism: self.mechanism.clone(),
+            response,
+        }
+    }
+}
+
+/// Single step SASL authenticator sending the OAuth2 initial response
+pub struct SaslAuthenticator {
+    mechanism: Mechanism,
+    response: String,
+}
+
+impl Authenticator for SaslAuthenticator {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        if challenge.is_empty() {
+            self.response.clone()
+        } else {
+            // A non empty challenge is an error description. Server expects a dummy response before failing authentication
+            error!("OAuth2 authentication failed: {}", String::from_utf8_lossy(challenge));
+            match self.mechanism {
+                Mechanism::XOAuth2 => "".to_owned(),
+                Mechanism::OAuthBearer => "\x01".to_owned(),
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{NaiveDateTime, Utc};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::session_pool::{SessionPool, SessionPools};
+use tests_bin::unit_tests;
+
+/// Name of the spool directory, created next to the store file
+pub const OUTBOX: &str = "outbox";
+
+/// Used to have distinct file names for messages queued at the same time by different threads
+static QUEUED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fully built message waiting to be written in its folder
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QueuedMessage {
+    /// Folder in which the message will be written
+    pub folder: String,
+    /// Full RFC 5322 message
+    pub content: String,
+    /// IMAP flags to set on message
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// IMAP internal date of message
+    #[serde(default)]
+    pub date: Option<NaiveDateTime>,
+    /// Account message is written to (the default one when not set)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub account: Option<String>,
+}
+
+/// On-disk spool of messages that couldn't be written to IMAP server.
+/// Each message is stored as a JSON file, which name starts with queuing time,
+/// allowing messages to be written in the order they were queued.
+#[unit_tests("outbox.rs")]
+pub struct Outbox {
+    pub path: PathBuf,
+    /// Number of messages queued since outbox was opened
+    enqueued: AtomicUsize,
+    /// Number of messages refused by server since outbox was opened
+    refused: AtomicUsize,
+}
+
+impl Outbox {
+    /// Creates the outbox associated to the given store file
+    pub fn next_to(store: &Path) -> Outbox {
+        let mut path = store.parent().unwrap_or(Path::new(".")).to_path_buf();
+        path.push(OUTBOX);
+        Outbox { path, enqueued: AtomicUsize::new(0), refused: AtomicUsize::new(0) }
+    }
+
+    /// Number of messages queued since outbox was opened
+    pub fn enqueued(&self) -> usize {
+        self.enqueued.load(Ordering::SeqCst)
+    }
+
+    /// Number of messages refused by server (and set aside) since outbox was opened
+    pub fn refused(&self) -> usize {
+        self.refused.load(Ordering::SeqCst)
+    }
+
+    /// Writes message in outbox.
+    pub fn enqueue(&self, message: &QueuedMessage) -> io::Result<()> {
+        self.write(message, "json")?;
+        self.enqueued.fetch_add(1, Ordering::SeqCst);
+        info!("Message queued in {} for folder {}", self.path.to_string_lossy(), message.folder);
+        Ok(())
+    }
+
+    /// Writes a message refused by server in outbox, with the `.bad` extension (like unreadable files),
+    /// for it to be kept without ever blocking outbox.
+    pub fn set_aside(&self, message: &QueuedMessage) -> io::Result<()> {
+        let file = self.write(message, "bad")?;
+        self.refused.fetch_add(1, Ordering::SeqCst);
+        warn!("Message for folder {} is set aside in {}", message.folder, file.to_string_lossy());
+        Ok(())
+    }
+
+    /// Writes message in a file having the given extension.
+    /// File is first written (and synced to disk) with a temporary name, then renamed,
+    /// to never have partially written messages in outbox.
+    fn write(&self, message: &QueuedMessage, extension: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.path)?;
+        let name = format!(
+            "{:015}-{:06}",
+            Utc::now().timestamp_millis(),
+            QUEUED_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let temporary = self.path.join(format!("{}.tmp", name));
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(serde_json::to_string(message)?.as_bytes())?;
+        file.sync_all()?;
+        let file = self.path.join(format!("{}.{}", name, extension));
+        fs::rename(&temporary, &file)?;
+        Ok(file)
+    }
+
+    /// Lists queued messages files, oldest first
+    fn queued(&self) -> io::Result<Vec<PathBuf>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|e| e == "json").unwrap_or(false))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Writes all queued messages to IMAP, removing them from outbox once written.
+    /// Flush stops at the first transport failure (as IMAP server is very probably still unavailable).
+    /// Files which can't be read (like ones truncated by a crash), and messages refused by server (which would be refused again),
+    /// are renamed with the `.bad` extension, and skipped.
+    /// Returns the number of written messages and the number of messages still in outbox.
+    pub fn flush(&self, pools: &SessionPools) -> io::Result<(usize, usize)> {
+        let queued = self.queued()?;
+        let mut written = 0;
+        let mut set_aside = 0;
+        for file in queued.iter() {
+            let message = match Outbox::read(file) {
+                Ok(message) => message,
+                Err(e) => {
+                    // An unreadable message would otherwise prevent all later messages from being written
+                    error!("Queued message {} can't be read due to {}. It is moved aside", file.to_string_lossy(), e);
+                    fs::rename(file, file.with_extension("bad"))?;
+                    set_aside += 1;
+                    continue;
+                }
+            };
+            let pool = match pools.get(&message.account) {
+                Some(pool) => pool,
+                None => {
+                    error!("Queued message {} is for unknown account {:?}. It is left in outbox",
+                        file.to_string_lossy(), message.account);
+                    continue;
+                }
+            };
+            let result = pool
+                .ensure_folders(std::slice::from_ref(&message.folder))
+                .and_then(|_| pool.append(&message.folder, &message.content, &message.flags, message.date));
+            match result {
+                Ok(_) => {
+                    fs::remove_file(file)?;
+                    written += 1;
+                }
+                Err(e) if SessionPool::is_connection_broken(&e) => {
+                    error!("Unable to write queued message {} due to {}", file.to_string_lossy(), e);
+                    break;
+                }
+                Err(e) => {
+                    error!("Queued message {} has been refused by server due to {}. It is moved aside", file.to_string_lossy(), e);
+                    fs::rename(file, file.with_extension("bad"))?;
+                    self.refused.fetch_add(1, Ordering::SeqCst);
+                    set_aside += 1;
+                }
+            }
+        }
+        if written > 0 {
+            info!("{} queued messages have been written", written);
+        }
+        Ok((written, queued.len() - written - set_aside))
+    }
+
+    /// Reads a queued message file
+    fn read(file: &Path) -> io::Result<QueuedMessage> {
+        Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+    }
+}
@@ -0,0 +1,125 @@
+use tests_bin::unit_tests;
+
+/// Thresholds (in percents of IMAP storage quota) above which rrss2imap changes its behaviour.
+/// They apply to all IMAP accounts.
+#[unit_tests("quota.rs")]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct QuotaThresholds {
+    /// A warning is logged when usage is above that percentage (80 by default)
+    #[serde(default = "QuotaThresholds::default_warn")]
+    pub warn: u8,
+    /// When set, images are no more inlined in messages when usage is above that percentage
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub no_inline: Option<u8>,
+    /// When set, feeds written to the account are no more read when usage is above that percentage
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub stop: Option<u8>,
+}
+
+impl QuotaThresholds {
+    /// default warning threshold, used by serde
+    pub fn default_warn() -> u8 {
+        80
+    }
+
+    /// Check if usage is above warning threshold
+    pub fn warns(&self, usage: &Usage) -> bool {
+        usage.percent() >= self.warn as f64
+    }
+
+    /// Check if images should no more be inlined
+    pub fn disables_inlining(&self, usage: &Usage) -> bool {
+        self.no_inline.is_some_and(|threshold| usage.percent() >= threshold as f64)
+    }
+
+    /// Check if feeds should no more be read
+    pub fn stops(&self, usage: &Usage) -> bool {
+        self.stop.is_some_and(|threshold| usage.percent() >= threshold as f64)
+    }
+}
+
+/// Storage usage of an IMAP quota root (in kilobytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub root: String,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl Usage {
+    /// Used percentage of quota (a limit of 0 means there is no quota)
+    pub fn percent(&self) -> f64 {
+        if self.limit == 0 {
+            0.0
+        } else {
+            self.used as f64 * 100.0 / self.limit as f64
+        }
+    }
+}
+
+/// Splits an IMAP response line into atoms, quoted strings (unquoted) and parentheses
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\r' | '\n' => {},
+            '(' | ')' => tokens.push(c.to_string()),
+            '"' => {
+                let mut token = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => token.extend(chars.next()),
+                        '"' => break,
+                        _ => token.push(c),
+                    }
+                }
+                tokens.push(token);
+            },
+            _ => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c == ' ' || c == '(' || c == ')' || c == '\r' || c == '\n' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            },
+        }
+    }
+    tokens
+}
+
+/// Finds the tokens of the untagged response with the given name
+fn untagged(response: &[u8], name: &str) -> Option<Vec<String>> {
+    String::from_utf8_lossy(response)
+        .lines()
+        .map(tokens)
+        .find(|tokens| tokens.len() > 1 && tokens[0] == "*" && tokens[1].eq_ignore_ascii_case(name))
+        .map(|tokens| tokens[2..].to_vec())
+}
+
+/// Reads the (first) quota root of a `GETQUOTAROOT` response
+pub fn parse_quota_root(response: &[u8]) -> Option<String> {
+    // First token is the mailbox, and the following ones are its quota roots
+    untagged(response, "QUOTAROOT").and_then(|tokens| tokens.get(1).cloned())
+}
+
+/// Reads the storage usage of a `GETQUOTA` response
+pub fn parse_quota(response: &[u8]) -> Option<Usage> {
+    let tokens = untagged(response, "QUOTA")?;
+    let root = tokens.first()?.clone();
+    let position = tokens.iter().position(|token| token.eq_ignore_ascii_case("STORAGE"))?;
+    Some(Usage {
+        root,
+        used: tokens.get(position + 1)?.parse().ok()?,
+        limit: tokens.get(position + 2)?.parse().ok()?,
+    })
+}
+
+/// Quotes the given quota root to use it in a command
+pub fn quote(root: &str) -> String {
+    format!("\"{}\"", root.replace('\\', "\\\\").replace('"', "\\\""))
+}
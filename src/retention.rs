@@ -0,0 +1,105 @@
+use chrono::{Duration, NaiveDate};
+use imap::types::Uid;
+use std::collections::HashSet;
+use tests_bin::unit_tests;
+
+/// Header set on all messages written by rrss2imap. Only messages having it are ever removed.
+pub const ENTRY_HEADER: &str = "X-RSS-Entry-Id";
+
+/// Header set on all messages written by rrss2imap, containing the url of the feed message comes from
+pub const FEED_HEADER: &str = "X-RSS-Feed-Url";
+
+/// Defines which messages are removed from a feed IMAP folder.
+/// Messages are removed when they're older than `max_age_days`, or when there are more than `max_count` messages.
+/// Flagged messages are never removed.
+#[unit_tests("retention.rs")]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Retention {
+    /// Messages older than that number of days (according to their IMAP internal date) are removed
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_age_days: Option<u32>,
+    /// Only that number of messages are kept (the most recently written ones)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_count: Option<usize>,
+    /// When set, removed messages are moved to that folder instead of being deleted
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub trash: Option<String>,
+}
+
+impl Retention {
+    /// IMAP search query matching all messages written by rrss2imap
+    pub fn written_query() -> String {
+        format!("HEADER {} \"\"", ENTRY_HEADER)
+    }
+
+    /// IMAP search query matching messages written by rrss2imap which can be removed
+    pub fn removable_query() -> String {
+        format!("UNFLAGGED {}", Retention::written_query())
+    }
+
+    /// IMAP search query matching removable messages which are too old, if there is a max age
+    pub fn too_old_query(&self, today: NaiveDate) -> Option<String> {
+        self.max_age_days.map(|days| {
+            let limit = today - Duration::days(days as i64);
+            format!("{} BEFORE {}", Retention::removable_query(), limit.format("%-d-%b-%Y"))
+        })
+    }
+
+    /// Computes the uids of messages to remove, given the uids of all messages written by rrss2imap,
+    /// the ones which can be removed, and the ones which are too old.
+    /// As uids grow with each appended message, the ones in excess are the lowest ones.
+    pub fn expired(&self, written: &HashSet<Uid>, removable: &HashSet<Uid>, too_old: &HashSet<Uid>) -> Vec<Uid> {
+        let mut expired: HashSet<Uid> = too_old.intersection(removable).cloned().collect();
+        if let Some(max_count) = self.max_count {
+            let mut sorted: Vec<&Uid> = written.iter().collect();
+            sorted.sort();
+            let excess = sorted.len().saturating_sub(max_count);
+            expired.extend(sorted.into_iter().take(excess).filter(|uid| removable.contains(uid)));
+        }
+        let mut expired: Vec<Uid> = expired.into_iter().collect();
+        expired.sort();
+        expired
+    }
+}
+
+/// Formats uids as an IMAP sequence set
+pub fn uid_set(uids: &[Uid]) -> String {
+    uids.iter().map(|uid| uid.to_string()).collect::<Vec<String>>().join(",")
+}
+
+/// Makes the given value usable in a header : control characters (which would allow to inject other headers)
+/// are replaced by spaces, and surrounding spaces are removed (as they're lost when header is read)
+pub fn header_safe(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// IMAP search query matching messages having the given value in the given header.
+/// As IMAP header search matches substrings, found messages must then be checked with `header_value`.
+pub fn header_query(header: &str, value: &str) -> String {
+    format!("HEADER {} \"{}\"", header, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads the value of the given header in the raw header lines fetched from server
+/// (folded lines are unfolded)
+pub fn header_value(fields: &[u8], header: &str) -> Option<String> {
+    let fields = String::from_utf8_lossy(fields);
+    let mut unfolded: Vec<String> = vec![];
+    for line in fields.lines() {
+        match (line.strip_prefix(['\t', ' ']), unfolded.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => unfolded.push(line.to_owned()),
+        }
+    }
+    unfolded.iter().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case(header) {
+            Some(value.trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
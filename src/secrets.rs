@@ -0,0 +1,118 @@
+use age::secrecy::SecretString;
+use age::{Decryptor, Encryptor, IdentityFile};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{Read, Write};
+use std::iter;
+use std::path::PathBuf;
+use custom_error::custom_error;
+use tests_bin::unit_tests;
+
+custom_error!{pub UnusableSecrets
+    NoKey{ variable:String } = "No key available for secrets: no identity_file is set, and {variable} environment variable is empty",
+    IO{ source:std::io::Error } = "Unable to read secrets",
+    Decrypt{ source:age::DecryptError } = "Unable to decrypt secrets",
+    Encrypt{ source:age::EncryptError } = "Unable to encrypt secrets",
+    JsonParseError{ source:serde_json::Error } = "Can't parse decrypted secrets"
+}
+
+/// Environment variable containing the passphrase used when no identity file is set
+pub const PASSPHRASE: &str = "RRSS2IMAP_PASSPHRASE";
+/// Environment variable containing the new passphrase, used when secrets are rotated
+pub const NEW_PASSPHRASE: &str = "RRSS2IMAP_NEW_PASSPHRASE";
+
+/// Secret values (like IMAP password or OAuth2 refresh token) stored encrypted in config file.
+/// Values are stored as a JSON object encrypted with [age](https://age-encryption.org),
+/// using either an identity file (as generated by `age-keygen`) or the passphrase
+/// given in `RRSS2IMAP_PASSPHRASE` environment variable.
+/// They're only decrypted by commands using them, and never written in clear.
+#[unit_tests("secrets.rs")]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Secrets {
+    /// age identity file used to decrypt secrets. When not set, a passphrase is used
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub identity_file: Option<PathBuf>,
+    /// armored age encrypted secret values
+    #[serde(default)]
+    pub encrypted: String,
+    /// clear secret values, only available once unlocked
+    #[serde(skip)]
+    values: BTreeMap<String, String>,
+}
+
+impl Secrets {
+    /// Get the clear value of the given secret
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.values.get(name)
+    }
+
+    /// Names of all the stored secrets
+    pub fn names(&self) -> Vec<&String> {
+        self.values.keys().collect()
+    }
+
+    /// Decrypts the stored values
+    pub fn unlock(&mut self) -> Result<(), UnusableSecrets> {
+        if self.encrypted.is_empty() {
+            self.values.clear();
+            return Ok(());
+        }
+        let decryptor = Decryptor::new_buffered(age::armor::ArmoredReader::new(self.encrypted.as_bytes()))?;
+        let mut reader = match &self.identity_file {
+            Some(file) => {
+                let identities = IdentityFile::from_file(file.to_string_lossy().to_string())?.into_identities()?;
+                decryptor.decrypt(identities.iter().map(|i| i.as_ref()))?
+            },
+            None => {
+                let identity = age::scrypt::Identity::new(Secrets::passphrase(PASSPHRASE)?);
+                decryptor.decrypt(iter::once(&identity as &dyn age::Identity))?
+            },
+        };
+        let mut clear = vec![];
+        reader.read_to_end(&mut clear)?;
+        self.values = serde_json::from_slice(&clear)?;
+        Ok(())
+    }
+
+    /// Sets the value of a secret, and encrypts again all values
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), UnusableSecrets> {
+        self.values.insert(name.to_owned(), value.to_owned());
+        self.lock(PASSPHRASE)
+    }
+
+    /// Encrypts again all values with a new key.
+    /// When an identity file is given, it replaces the current key (whatever it was).
+    /// Otherwise, when secrets use a passphrase, the new one is read from `RRSS2IMAP_NEW_PASSPHRASE`
+    /// (and when they use an identity file, they're encrypted again with it).
+    pub fn rotate(&mut self, identity_file: Option<PathBuf>) -> Result<(), UnusableSecrets> {
+        if identity_file.is_some() {
+            self.identity_file = identity_file;
+        }
+        self.lock(NEW_PASSPHRASE)
+    }
+
+    /// Encrypts values, with the passphrase read from the given environment variable when there is no identity file
+    fn lock(&mut self, passphrase_variable: &str) -> Result<(), UnusableSecrets> {
+        let encryptor = match &self.identity_file {
+            Some(file) => {
+                let recipients = IdentityFile::from_file(file.to_string_lossy().to_string())?.to_recipients()?;
+                Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn age::Recipient))?
+            },
+            None => Encryptor::with_user_passphrase(Secrets::passphrase(passphrase_variable)?),
+        };
+        let mut encrypted = vec![];
+        let armored = age::armor::ArmoredWriter::wrap_output(&mut encrypted, age::armor::Format::AsciiArmor)?;
+        let mut writer = encryptor.wrap_output(armored)?;
+        writer.write_all(serde_json::to_string(&self.values)?.as_bytes())?;
+        writer.finish()?.finish()?;
+        self.encrypted = String::from_utf8_lossy(&encrypted).to_string();
+        Ok(())
+    }
+
+    fn passphrase(variable: &str) -> Result<SecretString, UnusableSecrets> {
+        match env::var(variable) {
+            Ok(passphrase) if !passphrase.is_empty() => Ok(SecretString::from(passphrase)),
+            _ => Err(UnusableSecrets::NoKey { variable: variable.to_owned() }),
+        }
+    }
+}
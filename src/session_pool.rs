@@ -0,0 +1,506 @@
+use chrono::{NaiveDateTime, Utc};
+use imap::error::{Error, ParseError, Result};
+use imap::types::Uid;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::{thread, time};
+use tests_bin::unit_tests;
+
+use super::imap_state::{State, StoredState, UnusableState};
+use super::quota::{self, Usage};
+use super::retention::{self, uid_set, Retention, ENTRY_HEADER, FEED_HEADER};
+use super::settings::{Email, Imap, Settings};
+
+/// A small pool of authenticated IMAP sessions shared by all rayon workers.
+///
+/// Sessions are opened lazily (so a run with nothing to write never connects),
+/// given back to the pool once used, and dropped when the server seems to have closed them,
+/// in which case the next use transparently reconnects.
+/// As each worker only holds one session at a time, the pool never grows bigger than the number of workers.
+#[unit_tests("session_pool.rs")]
+pub struct SessionPool<'a> {
+    email: &'a Email,
+    sessions: Mutex<Vec<Imap>>,
+    /// Folders known to exist on server (and server hierarchy delimiter), read once per run
+    /// (or the reason why they couldn't be read, to not try again for each message)
+    folders: Mutex<Option<std::result::Result<Folders, String>>>,
+    /// Held while folders are listed or created, so workers needing no new folder never wait for server
+    updating_folders: Mutex<()>,
+    /// Capabilities already asked to server (and whether server has them)
+    capabilities: Mutex<HashMap<String, bool>>,
+    /// Why server couldn't be reached, once all attempts to run an operation failed at transport level
+    unreachable: Mutex<Option<String>>,
+}
+
+/// Folders existing on server, as listed at the beginning of a run
+struct Folders {
+    delimiter: Option<String>,
+    existing: HashSet<String>,
+}
+
+impl Folders {
+    /// Reads folders listed by server (as (name, hierarchy delimiter) pairs)
+    fn listed(listed: &[(String, Option<String>)]) -> Folders {
+        let delimiter = listed.iter().find_map(|(_, delimiter)| delimiter.clone());
+        Folders {
+            delimiter,
+            existing: listed.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+}
+
+impl<'a> SessionPool<'a> {
+    pub fn new(email: &'a Email) -> SessionPool<'a> {
+        SessionPool {
+            email,
+            sessions: Mutex::new(vec![]),
+            folders: Mutex::new(None),
+            updating_folders: Mutex::new(()),
+            capabilities: Mutex::new(HashMap::new()),
+            unreachable: Mutex::new(None),
+        }
+    }
+
+    /// Checks if server has the given capability (server is only asked once per run)
+    pub fn has_capability(&self, capability: &str) -> Result<bool> {
+        if let Some(known) = self.capabilities.lock().unwrap().get(capability) {
+            return Ok(*known);
+        }
+        let found = self.with_session(|imap| imap.has_capability(capability))?;
+        self.capabilities.lock().unwrap().insert(capability.to_owned(), found);
+        Ok(found)
+    }
+
+    /// Email config of the account this pool connects to
+    pub fn email(&self) -> &'a Email {
+        self.email
+    }
+
+    /// Make sure all the given folders exist on server, creating the missing ones
+    /// (and their missing parents) when email config allows it.
+    /// Server folders are listed only once, and the list is then kept up to date with created folders.
+    /// When they can't be listed, later calls fail immediately.
+    pub fn ensure_folders(&self, folders: &[String]) -> Result<()> {
+        if !self.email.create_missing_folders {
+            return Ok(());
+        }
+        if let Some(missing) = self.missing(folders)? {
+            if missing.is_empty() {
+                return Ok(());
+            }
+        }
+        let _updating = self.updating_folders.lock().unwrap();
+        // Another worker may have listed (or created) folders while this one was waiting
+        if self.folders.lock().unwrap().is_none() {
+            let listed = self.with_session(|imap| imap.list());
+            let known = listed.as_ref().map(|listed| Folders::listed(listed)).map_err(|e| e.to_string());
+            if let Ok(known) = &known {
+                debug!("Server {} uses {:?} as hierarchy delimiter", self.email.server, known.delimiter);
+            }
+            *self.folders.lock().unwrap() = Some(known);
+            listed?;
+        }
+        for missing in self.missing(folders)?.unwrap_or_default() {
+            info!("Creating missing folder {}", missing);
+            self.with_session(|imap| imap.create(&missing))?;
+            if self.email.subscribe_created_folders {
+                self.with_session(|imap| imap.subscribe(&missing))?;
+            }
+            if let Some(Ok(known)) = self.folders.lock().unwrap().as_mut() {
+                known.existing.insert(missing);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds which of the given folders (and their parents) don't exist on server, parents first.
+    /// Returns None when server folders have not been listed yet.
+    fn missing(&self, folders: &[String]) -> Result<Option<Vec<String>>> {
+        match self.folders.lock().unwrap().as_ref() {
+            None => Ok(None),
+            Some(Err(reason)) => Err(Error::Io(std::io::Error::other(
+                format!("folders of {} couldn't be listed: {}", self.email.server, reason)))),
+            Some(Ok(known)) => {
+                let mut missing: Vec<String> = vec![];
+                for folder in folders {
+                    for folder in missing_folders(&known.existing, folder, &known.delimiter) {
+                        if !missing.contains(&folder) {
+                            missing.push(folder);
+                        }
+                    }
+                }
+                Ok(Some(missing))
+            },
+        }
+    }
+
+    /// Runs the given operation with one of the pooled sessions (or a fresh one if all are in use).
+    /// Failed operations are retried according to the email `retry_max_count` and `retry_delay`,
+    /// with a new session when the previous one looks broken.
+    /// When all attempts failed at transport level, server is considered unreachable for the rest of the run,
+    /// and later operations fail immediately (instead of each one waiting for all its attempts).
+    pub fn with_session<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(&mut Imap) -> Result<T>,
+    {
+        let mut count = 0;
+        loop {
+            count += 1;
+            let result = self.attempt(&mut operation);
+            match result {
+                Err(e) if count <= self.email.retry_max_count => {
+                    error!(
+                        "Previous IMAP attempt failed with {}. Retrying ({}/{}) in {} s.!",
+                        e, count, self.email.retry_max_count, self.email.retry_delay
+                    );
+                    thread::sleep(time::Duration::from_secs(self.email.retry_delay));
+                }
+                Err(e) if SessionPool::is_connection_broken(&e) => {
+                    *self.unreachable.lock().unwrap() = Some(e.to_string());
+                    return Err(e);
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// Runs the given operation once with one of the pooled sessions (or a fresh one if all are in use).
+    /// Used for operations which can't be blindly run again (like copying messages, as a failure may happen
+    /// once messages have been copied, and copying them again would duplicate them).
+    pub fn with_session_once<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(&mut Imap) -> Result<T>,
+    {
+        self.attempt(&mut operation)
+    }
+
+    /// Runs the given operation once, dropping session when it looks broken
+    fn attempt<T, F>(&self, operation: &mut F) -> Result<T>
+    where
+        F: FnMut(&mut Imap) -> Result<T>,
+    {
+        if let Some(reason) = self.unreachable.lock().unwrap().as_ref() {
+            return Err(Error::Io(std::io::Error::other(format!("{} is unreachable: {}", self.email.server, reason))));
+        }
+        self.acquire().and_then(|mut imap| {
+            let result = operation(&mut imap);
+            match &result {
+                Err(e) if SessionPool::is_connection_broken(e) => {
+                    debug!("Dropping IMAP session to {} due to {}", self.email.server, e)
+                }
+                _ => self.release(imap),
+            }
+            result
+        })
+    }
+
+    /// Appends a new message to the given mailbox, using a pooled session
+    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(&self, mailbox: &S, content: &B, flags: &[String], date: Option<NaiveDateTime>) -> Result<()> {
+        self.with_session(|imap| imap.append(mailbox, content, flags, date))
+    }
+
+    /// Checks if a message with the given Message-ID already exists in mailbox (a missing mailbox contains no message)
+    pub fn contains_message(&self, mailbox: &str, message_id: &str) -> Result<bool> {
+        self.with_session(|imap| {
+            match imap.select(mailbox) {
+                Err(Error::No(_)) => return Ok(false),
+                selected => selected?,
+            };
+            imap.uid_search(format!("HEADER Message-ID \"{}\"", message_id)).map(|uids| !uids.is_empty())
+        })
+    }
+
+    /// Checks if the entry with exactly the given id has been written in mailbox (a missing mailbox contains no entry)
+    pub fn contains_entry(&self, mailbox: &str, entry_id: &str) -> Result<bool> {
+        self.with_session(|imap| {
+            match imap.select(mailbox) {
+                Err(Error::No(_)) => return Ok(false),
+                selected => selected?,
+            };
+            SessionPool::find_by_header(imap, ENTRY_HEADER, entry_id).map(|uids| !uids.is_empty())
+        })
+    }
+
+    /// Reads feeds state from the last state message of mailbox (an empty state when there is none)
+    pub fn read_state(&self, mailbox: &str) -> std::result::Result<StoredState, UnusableState> {
+        let found = self.with_session(|imap| {
+            match imap.select(mailbox) {
+                Err(Error::No(_)) => return Ok(None),
+                selected => selected?,
+            };
+            match imap.uid_search(State::query())?.into_iter().max() {
+                Some(uid) => Ok(Some((uid, imap.uid_fetch_text(uid)?))),
+                None => Ok(None),
+            }
+        })?;
+        match found {
+            Some((uid, Some(body))) => Ok(StoredState { state: State::from_body(&body)?, uid: Some(uid) }),
+            Some((_, None)) => Err(UnusableState::NoBody),
+            None => Ok(StoredState { state: State::default(), uid: None }),
+        }
+    }
+
+    /// Replaces state messages of mailbox with the given state, provided the last one still has the expected uid
+    /// (otherwise, another machine wrote state meanwhile, and a `Conflict` error is returned).
+    /// As appending twice would write state twice, it is never retried.
+    pub fn write_state(&self, mailbox: &str, state: &State, expected: Option<Uid>) -> std::result::Result<(), UnusableState> {
+        let message = state.to_message()?;
+        self.ensure_folders(&[mailbox.to_owned()])?;
+        let current: Vec<Uid> = self.with_session(|imap| {
+            imap.select(mailbox)?;
+            Ok(imap.uid_search(State::query())?.into_iter().collect())
+        })?;
+        if current.iter().max().cloned() != expected {
+            return Err(UnusableState::Conflict);
+        }
+        self.with_session_once(|imap| imap.append(mailbox, &message, &["\\Seen".to_owned()], None))?;
+        // Only the last state message is read, so previous ones can as well be removed on next write
+        if !current.is_empty() {
+            if let Err(e) = self.delete(mailbox, &uid_set(&current)) {
+                warn!("{}\nUnable to remove previous state messages from {}. They will be removed next time", e, mailbox);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds (in selected mailbox) the sorted uids of messages having exactly the given value in the given header.
+    /// Candidates found by IMAP search (which matches substrings) have their header fetched to be checked.
+    fn find_by_header(imap: &mut Imap, header: &str, value: &str) -> Result<Vec<Uid>> {
+        // Value is written the way it was when message was built
+        let value = retention::header_safe(value);
+        let value = value.as_str();
+        let mut candidates: Vec<Uid> = imap.uid_search(retention::header_query(header, value))?.into_iter().collect();
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        candidates.sort();
+        let mut uids: Vec<Uid> = imap.uid_fetch_header(uid_set(&candidates), header)?
+            .into_iter()
+            .filter(|(_, fields)| retention::header_value(fields, header).as_deref() == Some(value))
+            .map(|(uid, _)| uid)
+            .collect();
+        uids.sort();
+        Ok(uids)
+    }
+
+    /// Moves all messages of the given feed (having exactly its url in their `X-RSS-Feed-Url` header) from mailbox to the target one.
+    /// When server doesn't support MOVE, messages are copied, then deleted (see `transfer`).
+    /// Returns the number of moved messages.
+    pub fn move_feed_messages(&self, mailbox: &str, target: &str, feed_url: &str) -> Result<usize> {
+        self.ensure_folders(&[target.to_owned()])?;
+        let uids = self.with_session(|imap| {
+            imap.select(mailbox)?;
+            SessionPool::find_by_header(imap, FEED_HEADER, feed_url)
+        })?;
+        if !uids.is_empty() {
+            self.transfer(mailbox, &uid_set(&uids), target)?;
+        }
+        Ok(uids.len())
+    }
+
+    /// Reads storage usage of INBOX quota root (None when server has no quota)
+    pub fn quota(&self) -> Result<Option<Usage>> {
+        let root = match self.raw_command("GETQUOTAROOT INBOX") {
+            Ok(response) => quota::parse_quota_root(&response),
+            // Server doesn't support QUOTA extension
+            Err(Error::Bad(_)) | Err(Error::No(_)) => None,
+            Err(e) => return Err(e),
+        };
+        match root {
+            Some(root) => Ok(quota::parse_quota(&self.raw_command(format!("GETQUOTA {}", quota::quote(&root)))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Runs a command which response may not be understood by IMAP library, and returns the raw response.
+    /// When library can't parse response, it is read from the parse error, and the session
+    /// (which still has unread response lines) is dropped.
+    fn raw_command<S: AsRef<str>>(&self, command: S) -> Result<Vec<u8>> {
+        let mut imap = self.acquire()?;
+        match imap.run_command(command) {
+            Err(Error::Parse(ParseError::Invalid(response))) => Ok(response),
+            result => {
+                if !matches!(&result, Err(e) if SessionPool::is_connection_broken(e)) {
+                    self.release(imap);
+                }
+                result
+            },
+        }
+    }
+
+    /// Finds the UIDVALIDITY of mailbox and the uid of the message having exactly the given Message-ID in it
+    /// (the most recent one if there are several)
+    pub fn locate_message(&self, mailbox: &str, message_id: &str) -> Result<Option<(u32, Uid)>> {
+        self.with_session(|imap| {
+            let uid_validity = imap.select(mailbox)?;
+            let uids = SessionPool::find_by_header(imap, "Message-ID", &format!("<{}>", message_id))?;
+            Ok(uid_validity.zip(uids.last().copied()))
+        })
+    }
+
+    /// Removes the message with the given uid from mailbox, provided mailbox UIDVALIDITY is still the given one.
+    /// Returns false when UIDVALIDITY changed (in which case nothing is removed)
+    pub fn remove_message(&self, mailbox: &str, uid_validity: u32, uid: Uid) -> Result<bool> {
+        let uidplus = self.has_capability("UIDPLUS")?;
+        self.with_session(|imap| {
+            if imap.select(mailbox)? != Some(uid_validity) {
+                return Ok(false);
+            }
+            SessionPool::expunge(imap, &uid_set(&[uid]), uidplus)?;
+            Ok(true)
+        })
+    }
+
+    /// Removes the messages of mailbox expired according to the given retention.
+    /// Returns the number of removed messages.
+    pub fn prune(&self, mailbox: &str, retention: &Retention) -> Result<usize> {
+        if let Some(trash) = &retention.trash {
+            self.ensure_folders(std::slice::from_ref(trash))?;
+        }
+        let expired = self.with_session(|imap| {
+            imap.select(mailbox)?;
+            let written = imap.uid_search(Retention::written_query())?;
+            let removable = imap.uid_search(Retention::removable_query())?;
+            let too_old = match retention.too_old_query(Utc::now().date_naive()) {
+                Some(query) => imap.uid_search(query)?,
+                None => HashSet::new(),
+            };
+            Ok(retention.expired(&written, &removable, &too_old))
+        })?;
+        if !expired.is_empty() {
+            let uids = uid_set(&expired);
+            match &retention.trash {
+                Some(trash) => self.transfer(mailbox, &uids, trash)?,
+                None => self.delete(mailbox, &uids)?,
+            }
+        }
+        Ok(expired.len())
+    }
+
+    /// Moves messages having the given uids from mailbox to the target one.
+    /// When server doesn't support MOVE, messages are copied, then deleted.
+    /// As copying messages twice would duplicate them, it is never retried.
+    fn transfer(&self, mailbox: &str, uids: &str, target: &str) -> Result<()> {
+        let movable = self.has_capability("MOVE")?;
+        let uidplus = self.has_capability("UIDPLUS")?;
+        self.with_session_once(|imap| {
+            imap.select(mailbox)?;
+            if movable {
+                imap.uid_mv(uids, target)
+            } else {
+                debug!("{} doesn't support MOVE. Messages are copied, then deleted", self.email.server);
+                imap.uid_copy(uids, target)?;
+                SessionPool::expunge(imap, uids, uidplus)
+            }
+        })
+    }
+
+    /// Deletes messages having the given uids from mailbox
+    fn delete(&self, mailbox: &str, uids: &str) -> Result<()> {
+        let uidplus = self.has_capability("UIDPLUS")?;
+        self.with_session(|imap| {
+            imap.select(mailbox)?;
+            SessionPool::expunge(imap, uids, uidplus)
+        })
+    }
+
+    /// Flags messages having the given uids (in selected mailbox) as deleted, and removes them.
+    /// Without UIDPLUS (which allows to only remove them), all deleted messages of mailbox are removed.
+    fn expunge(imap: &mut Imap, uids: &str, uidplus: bool) -> Result<()> {
+        imap.uid_add_flags(uids, "\\Deleted")?;
+        if uidplus {
+            imap.uid_expunge(uids)
+        } else {
+            imap.expunge()
+        }
+    }
+
+    /// Cleanly closes all pooled sessions. Should be called once all messages have been written.
+    pub fn logout(&self) {
+        let sessions: Vec<Imap> = self.sessions.lock().unwrap().drain(..).collect();
+        for mut imap in sessions {
+            match imap.logout() {
+                Ok(_) => debug!("Logged out from {}", self.email.server),
+                Err(e) => warn!("Unable to log out from {} due to {}", self.email.server, e),
+            }
+        }
+    }
+
+    fn acquire(&self) -> Result<Imap> {
+        // Take care to release the lock before connecting, as connection may be slow
+        let pooled = self.sessions.lock().unwrap().pop();
+        match pooled {
+            Some(imap) => Ok(imap),
+            None => self.email.start(),
+        }
+    }
+
+    fn release(&self, imap: Imap) {
+        self.sessions.lock().unwrap().push(imap);
+    }
+
+    /// Check if error is an IMAP level one (in which case session can be reused)
+    /// or a transport level one (in which case session should be dropped).
+    pub fn is_connection_broken(error: &Error) -> bool {
+        !matches!(error, Error::No(_) | Error::Bad(_) | Error::Validate(_))
+    }
+}
+
+/// Session pools of all the configured accounts.
+/// The default account (the `email` settings) is the one without name.
+pub struct SessionPools<'a> {
+    pools: HashMap<Option<String>, SessionPool<'a>>,
+}
+
+impl<'a> SessionPools<'a> {
+    pub fn new(settings: &'a Settings) -> SessionPools<'a> {
+        let mut pools = HashMap::new();
+        pools.insert(None, SessionPool::new(&settings.email));
+        for (name, email) in settings.accounts.iter() {
+            pools.insert(Some(name.clone()), SessionPool::new(email));
+        }
+        SessionPools { pools }
+    }
+
+    /// Get the pool of the given account, if it exists
+    pub fn get(&self, account: &Option<String>) -> Option<&SessionPool<'a>> {
+        self.pools.get(account)
+    }
+
+    /// All accounts, with their pools
+    pub fn iter(&self) -> impl Iterator<Item = (&Option<String>, &SessionPool<'a>)> {
+        self.pools.iter()
+    }
+
+    /// Cleanly closes sessions of all accounts
+    pub fn logout(&self) {
+        for pool in self.pools.values() {
+            pool.logout();
+        }
+    }
+}
+
+/// Compute the list of folders to create (parents first) for the given folder to exist.
+/// Parents are obtained by cutting the folder name at each occurence of the server hierarchy delimiter.
+#[unit_tests("session_pool/can_find_missing_folders.rs")]
+pub fn missing_folders(existing: &HashSet<String>, folder: &str, delimiter: &Option<String>) -> Vec<String> {
+    if folder.is_empty() {
+        return vec![];
+    }
+    let mut hierarchy = vec![];
+    if let Some(delimiter) = delimiter.as_ref().filter(|d| !d.is_empty()) {
+        let mut position = 0;
+        while let Some(index) = folder[position..].find(delimiter.as_str()) {
+            hierarchy.push(folder[..position + index].to_owned());
+            position += index + delimiter.len();
+        }
+    }
+    hierarchy.push(folder.to_owned());
+    hierarchy
+        .into_iter()
+        .filter(|f| !f.is_empty())
+        // INBOX is case-insensitive and always exists
+        .filter(|f| !f.eq_ignore_ascii_case("INBOX"))
+        .filter(|f| !existing.contains(f))
+        .collect()
+}
@@ -1,21 +1,47 @@
-use imap::error::Result;
-use imap::Session;
-use std::{thread, time};
+use chrono::{FixedOffset, NaiveDateTime, TimeZone};
+use imap::error::{Error, Result};
+use imap::types::{Flag, Uid};
+use imap::{Client, Session};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs};
+use tests_bin::unit_tests;
 
 use super::config::Config;
+use super::oauth::OAuth;
+use super::quota::QuotaThresholds;
+use super::secrets::{Secrets, UnusableSecrets};
+use super::smtp::Smtp;
+use super::utf7;
 
 /// Secured connection or not ?
 /// Whichever is chosen, user has to give the port as parameter
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum Secure {
+    /// Plain text connection (typically on port 143). Password will go in clear over the network !
     No(u16),
+    /// TLS connection from the start (typically on port 993)
     Yes(u16),
+    /// Plain text connection upgraded to TLS using STARTTLS (typically on port 143)
+    StartTls(u16),
 }
+/// Minimum TLS protocol version accepted when connecting to IMAP server
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+}
+
 /// mail config
 /// I SHOULD allow a kind of Keepass access.
 /// But as code isn't expected to run on any kind of UI-aware machine (but on a headless Raspbian),
 /// I can't connect it to Keepass.
-/// So I should implement a kind of secure storage
+/// So credentials can be stored encrypted in the `secrets` block (see [Secrets](crate::secrets::Secrets))
+#[unit_tests("settings.rs")]
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Email {
     /// imap server we want to connect to
@@ -23,7 +49,9 @@ pub struct Email {
     /// username used to connect to that server
     pub user: String,
     /// password used to connect to that server.
-    /// **WARNING** THis password is in **no way** encrypted, which makes rrss2imap a "not-so-secured" software
+    /// **WARNING** THis password is in **no way** encrypted, which makes rrss2imap a "not-so-secured" software.
+    /// Prefer using `secrets`, `password_command`, `password_file` or `password_env`
+    #[serde(default)]
     pub password: String,
     /// secured connection state
     #[serde(default = "Email::default_secure")]
@@ -32,55 +60,230 @@ pub struct Email {
     pub retry_max_count: u8,
     #[serde(default = "Email::default_retry_delay")]
     pub retry_delay: u64,
+    /// when set to true, folders which don't exist on server are created prior to writing messages
+    #[serde(
+        skip_serializing_if = "Settings::is_true",
+        default = "Settings::default_true"
+    )]
+    pub create_missing_folders: bool,
+    /// when set to true, created folders are also subscribed to (to have them visible in mail clients)
+    #[serde(
+        skip_serializing_if = "Settings::is_false",
+        default = "Settings::default_false"
+    )]
+    pub subscribe_created_folders: bool,
+    /// when set to true, target folder is searched for message id prior to writing message, and message is not written
+    /// if it already exists (which prevents duplicates when feed state is lost)
+    #[serde(
+        skip_serializing_if = "Settings::is_false",
+        default = "Settings::default_false"
+    )]
+    pub check_duplicates: bool,
+    /// when set, OAuth2 is used to authenticate (instead of password)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub auth: Option<OAuth>,
+    /// when set, password is the first line output by this command (like `pass show imap`)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub password_command: Option<String>,
+    /// when set, password is the first line of this file
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub password_file: Option<PathBuf>,
+    /// when set, password is read from this environment variable
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub password_env: Option<String>,
+    /// encrypted credentials (`password`, and OAuth2 `refresh_token` and `client_secret`)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub secrets: Option<Secrets>,
+    /// PEM file containing the certificate of an additional trusted CA (like the one of a private CA),
+    /// used when connecting using TLS
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ca_file: Option<PathBuf>,
+    /// PEM file containing the client certificate, for servers requiring client authentication
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_cert: Option<PathBuf>,
+    /// PEM file containing the (PKCS#8) client private key, used with `client_cert`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_key: Option<PathBuf>,
+    /// minimum TLS version accepted (default is the one of TLS library)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_tls_version: Option<TlsVersion>,
+    /// when set to true, server certificate is NOT checked at all (which allows self-signed certificates).
+    /// **WARNING** anyone able to intercept connection will be able to read your password and messages
+    #[serde(
+        skip_serializing_if = "Settings::is_false",
+        default = "Settings::default_false"
+    )]
+    pub danger_accept_invalid_certs: bool,
 }
 
 /// Imap effective connection type (ie once connection has been established).
 /// This enum presents a simple interface allowing seamless access for (un)secured servers.
+/// Mailbox names are given and returned in plain UTF-8 : they're encoded in (and decoded from) IMAP modified UTF-7 here.
 #[derive(Debug)]
 pub enum Imap {
-    Secured(Session<native_tls::TlsStream<std::net::TcpStream>>),
-    Insecured(Session<std::net::TcpStream>),
+    Secured(Session<native_tls::TlsStream<TcpStream>>),
+    Insecured(Session<TcpStream>),
 }
 
 impl Imap {
     /// Appends a new message to the given server.
-    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(&mut self, mailbox: S, content: B) -> Result<()> {
+    /// Message will have the given flags set, and the given date (if any) as internal date.
+    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(&mut self, mailbox: S, content: B, flags: &[String], date: Option<NaiveDateTime>) -> Result<()> {
+        let flags: Vec<Flag> = flags.iter().map(|f| Flag::from(f.as_str())).collect();
+        let date = date.map(|d| FixedOffset::east_opt(0).unwrap().from_utc_datetime(&d));
+        let mailbox = utf7::encode(mailbox.as_ref());
         match self {
-            Imap::Secured(ref mut session) => session.append(mailbox, content),
-            Imap::Insecured(ref mut session) => session.append(mailbox, content),
+            Imap::Secured(ref mut session) => session.append_with_flags_and_date(mailbox, content, &flags, date),
+            Imap::Insecured(ref mut session) => session.append_with_flags_and_date(mailbox, content, &flags, date),
         }
     }
-}
 
-impl Email {
-    /// Appends a new message to the given server.
-    /// This method decorates the Imap::append method by adding retry ability.
-    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(&self, mailbox: &S, content: &B) -> Result<()> {
-        let mut count = 0;
-        loop {
-            count += 1;
-            let mut imap = self.start();
-            let result = imap.append(mailbox, content);
-            if result.is_err() {
-                if count > self.retry_max_count {
-                    return result;
-                } else {
-                    error!(
-                        "Previous append attempt failed with {}. Retrying ({}/{})in {} s.!",
-                        result.unwrap_err(),
-                        count,
-                        self.retry_max_count,
-                        self.retry_delay
-                    );
-                    // TODO maybe remove that once code is parallel
-                    thread::sleep(time::Duration::from_secs(self.retry_delay));
-                }
-            } else {
-                return result;
-            }
+    /// Lists all mailboxes of the server, as (name, hierarchy delimiter) pairs.
+    pub fn list(&mut self) -> Result<Vec<(String, Option<String>)>> {
+        let names = match self {
+            Imap::Secured(ref mut session) => session.list(Some(""), Some("*")),
+            Imap::Insecured(ref mut session) => session.list(Some(""), Some("*")),
+        }?;
+        Ok(names
+            .iter()
+            .map(|name| (Imap::decode_mailbox(name.name()), name.delimiter().map(|d| d.to_owned())))
+            .collect())
+    }
+
+    /// Creates the given mailbox
+    pub fn create<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+        let mailbox = utf7::encode(mailbox.as_ref());
+        match self {
+            Imap::Secured(ref mut session) => session.create(mailbox),
+            Imap::Insecured(ref mut session) => session.create(mailbox),
+        }
+    }
+
+    /// Subscribes to the given mailbox
+    pub fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+        let mailbox = utf7::encode(mailbox.as_ref());
+        match self {
+            Imap::Secured(ref mut session) => session.subscribe(mailbox),
+            Imap::Insecured(ref mut session) => session.subscribe(mailbox),
+        }
+    }
+
+    /// Selects the given mailbox, for later searches to happen in it.
+    /// Returns the mailbox UIDVALIDITY (when server gives it)
+    pub fn select<S: AsRef<str>>(&mut self, mailbox: S) -> Result<Option<u32>> {
+        let mailbox = utf7::encode(mailbox.as_ref());
+        match self {
+            Imap::Secured(ref mut session) => session.select(mailbox).map(|selected| selected.uid_validity),
+            Imap::Insecured(ref mut session) => session.select(mailbox).map(|selected| selected.uid_validity),
+        }
+    }
+
+    /// Searches the selected mailbox, returning uids of matching messages
+    pub fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
+        match self {
+            Imap::Secured(ref mut session) => session.uid_search(query),
+            Imap::Insecured(ref mut session) => session.uid_search(query),
+        }
+    }
+
+    /// Adds the given flags to messages having the given uids
+    pub fn uid_add_flags<S: AsRef<str>>(&mut self, uid_set: S, flags: &str) -> Result<()> {
+        let query = format!("+FLAGS.SILENT ({})", flags);
+        match self {
+            Imap::Secured(ref mut session) => session.uid_store(uid_set, query).map(|_| ()),
+            Imap::Insecured(ref mut session) => session.uid_store(uid_set, query).map(|_| ()),
+        }
+    }
+
+    /// Permanently removes deleted messages having the given uids
+    pub fn uid_expunge<S: AsRef<str>>(&mut self, uid_set: S) -> Result<()> {
+        match self {
+            Imap::Secured(ref mut session) => session.uid_expunge(uid_set).map(|_| ()),
+            Imap::Insecured(ref mut session) => session.uid_expunge(uid_set).map(|_| ()),
+        }
+    }
+
+    /// Permanently removes all deleted messages of the selected mailbox
+    pub fn expunge(&mut self) -> Result<()> {
+        match self {
+            Imap::Secured(ref mut session) => session.expunge().map(|_| ()),
+            Imap::Insecured(ref mut session) => session.expunge().map(|_| ()),
+        }
+    }
+
+    /// Fetches the body (without headers) of the message having the given uid
+    pub fn uid_fetch_text(&mut self, uid: Uid) -> Result<Option<Vec<u8>>> {
+        let fetched = match self {
+            Imap::Secured(ref mut session) => session.uid_fetch(uid.to_string(), "BODY.PEEK[TEXT]"),
+            Imap::Insecured(ref mut session) => session.uid_fetch(uid.to_string(), "BODY.PEEK[TEXT]"),
+        }?;
+        Ok(fetched.iter().find_map(|fetch| fetch.text().map(|text| text.to_vec())))
+    }
+
+    /// Fetches the given header of messages having the given uids, as (uid, raw header lines) pairs
+    pub fn uid_fetch_header<S: AsRef<str>>(&mut self, uid_set: S, header: &str) -> Result<Vec<(Uid, Vec<u8>)>> {
+        let query = format!("BODY.PEEK[HEADER.FIELDS ({})]", header);
+        let fetched = match self {
+            Imap::Secured(ref mut session) => session.uid_fetch(uid_set, query),
+            Imap::Insecured(ref mut session) => session.uid_fetch(uid_set, query),
+        }?;
+        Ok(fetched.iter()
+            .filter_map(|fetch| fetch.uid.zip(fetch.header().map(|fields| fields.to_vec())))
+            .collect())
+    }
+
+    /// Copies messages having the given uids to the given mailbox
+    pub fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(&mut self, uid_set: S1, mailbox: S2) -> Result<()> {
+        let mailbox = utf7::encode(mailbox.as_ref());
+        match self {
+            Imap::Secured(ref mut session) => session.uid_copy(uid_set, mailbox),
+            Imap::Insecured(ref mut session) => session.uid_copy(uid_set, mailbox),
+        }
+    }
+
+    /// Runs the given command, and returns the raw server response
+    pub fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<Vec<u8>> {
+        match self {
+            Imap::Secured(ref mut session) => session.run_command_and_read_response(command),
+            Imap::Insecured(ref mut session) => session.run_command_and_read_response(command),
+        }
+    }
+
+    /// Moves messages having the given uids to the given mailbox
+    pub fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(&mut self, uid_set: S1, mailbox: S2) -> Result<()> {
+        let mailbox = utf7::encode(mailbox.as_ref());
+        match self {
+            Imap::Secured(ref mut session) => session.uid_mv(uid_set, mailbox),
+            Imap::Insecured(ref mut session) => session.uid_mv(uid_set, mailbox),
+        }
+    }
+
+    /// Checks if server has the given capability
+    pub fn has_capability(&mut self, capability: &str) -> Result<bool> {
+        match self {
+            Imap::Secured(ref mut session) => session.capabilities().map(|capabilities| capabilities.has_str(capability)),
+            Imap::Insecured(ref mut session) => session.capabilities().map(|capabilities| capabilities.has_str(capability)),
         }
     }
 
+    /// Decodes a mailbox name listed by server, keeping it as is when it is not valid modified UTF-7
+    fn decode_mailbox(name: &str) -> String {
+        utf7::decode(name).unwrap_or_else(|| {
+            warn!("Mailbox name {} is not valid modified UTF-7, using it as is", name);
+            name.to_owned()
+        })
+    }
+
+    /// Closes the session on server side.
+    pub fn logout(&mut self) -> Result<()> {
+        match self {
+            Imap::Secured(ref mut session) => session.logout(),
+            Imap::Insecured(ref mut session) => session.logout(),
+        }
+    }
+}
+
+impl Email {
     /// default secure port, used by serde
     pub fn default_secure() -> Secure {
         Secure::Yes(993)
@@ -102,43 +305,215 @@ impl Email {
             secure: Email::default_secure(),
             retry_max_count: Email::default_retry_max_count(),
             retry_delay: Email::default_retry_delay(),
+            create_missing_folders: true,
+            subscribe_created_folders: false,
+            check_duplicates: false,
+            auth: None,
+            password_command: None,
+            password_file: None,
+            password_env: None,
+            secrets: None,
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            min_tls_version: None,
+            danger_accept_invalid_certs: false,
+        }
+    }
+
+    /// Decrypts secrets, if any
+    pub fn unlock_secrets(&mut self) -> std::result::Result<(), UnusableSecrets> {
+        match &mut self.secrets {
+            Some(secrets) => secrets.unlock(),
+            None => Ok(()),
         }
     }
 
+    /// Replaces the configured OAuth2 refresh token with the one the token endpoint rotated it to, if any.
+    /// New token is stored encrypted when this account has secrets, and in clear text otherwise.
+    /// Returns true when config changed.
+    pub fn keep_rotated_token(&mut self) -> std::result::Result<bool, UnusableSecrets> {
+        let auth = match &mut self.auth {
+            Some(auth) => auth,
+            None => return Ok(false),
+        };
+        let rotated = match auth.rotated_refresh_token() {
+            Some(rotated) => rotated,
+            None => return Ok(false),
+        };
+        match &mut self.secrets {
+            Some(secrets) => {
+                if secrets.get("refresh_token") == Some(&rotated) {
+                    return Ok(false);
+                }
+                secrets.set("refresh_token", &rotated)?;
+                auth.refresh_token.clear();
+            },
+            None => {
+                if auth.refresh_token == rotated {
+                    return Ok(false);
+                }
+                auth.refresh_token = rotated;
+            },
+        }
+        info!("Refresh token of {} has been replaced by the one sent by token endpoint", self.server);
+        Ok(true)
+    }
+
+    fn secret(&self, name: &str) -> Option<String> {
+        self.secrets.as_ref().and_then(|secrets| secrets.get(name)).cloned()
+    }
+
+    /// Get the password from the first defined source among command, file, environment variable,
+    /// secrets and (finally) the clear text `password` field.
+    pub fn resolve_password(&self) -> io::Result<String> {
+        if let Some(command) = &self.password_command {
+            let output = if cfg!(target_family = "windows") {
+                Command::new("cmd").arg("/C").arg(command).output()
+            } else {
+                Command::new("sh").arg("-c").arg(command).output()
+            }?;
+            if !output.status.success() {
+                return Err(io::Error::other(
+                    format!("password command \"{}\" failed with {}", command, output.status),
+                ));
+            }
+            Ok(Email::first_line(&String::from_utf8_lossy(&output.stdout)))
+        } else if let Some(file) = &self.password_file {
+            Ok(Email::first_line(&fs::read_to_string(file)?))
+        } else if let Some(variable) = &self.password_env {
+            env::var(variable).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("password environment variable {} can't be read: {}", variable, e),
+                )
+            })
+        } else if let Some(password) = self.secret("password") {
+            Ok(password)
+        } else {
+            Ok(self.password.clone())
+        }
+    }
+
+    fn first_line(text: &str) -> String {
+        text.lines().next().unwrap_or("").to_owned()
+    }
+
     /// starts connection to selected imap server, whatever it is
-    pub fn start(&self) -> Imap {
+    pub fn start(&self) -> Result<Imap> {
         match self.secure {
             Secure::Yes(port) => self.start_secure(port),
-            Secure::No(_port) => panic!("rrss2map no more supports unsecured connection to IMAP server due to evolutions of IMAP library (see https://github.com/jonhoo/rust-imap/pull/140)"),
+            Secure::StartTls(port) => self.start_starttls(port),
+            Secure::No(port) => self.start_insecure(port),
         }
     }
 
-    fn start_secure(&self, port: u16) -> Imap {
-        let tls = native_tls::TlsConnector::builder()
-            .build()
-            .expect("Couldn't create TLS connector");
+    /// Builds the TLS connector, using the custom TLS settings
+    pub fn tls_connector(&self) -> Result<native_tls::TlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(ca_file) = &self.ca_file {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(&fs::read(ca_file)?)?);
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                builder.identity(native_tls::Identity::from_pkcs8(&fs::read(cert)?, &fs::read(key)?)?);
+            },
+            (None, None) => {},
+            _ => warn!("Both client_cert and client_key have to be set to use a client certificate. None will be used"),
+        }
+        if let Some(version) = &self.min_tls_version {
+            builder.min_protocol_version(Some(match version {
+                TlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+                TlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+                TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+            }));
+        }
+        if self.danger_accept_invalid_certs {
+            warn!("!!! Certificate of {} is NOT checked. Anyone able to intercept connection can read your password !!!",
+                self.server);
+            builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder.build()?)
+    }
+
+    fn start_secure(&self, port: u16) -> Result<Imap> {
+        let tls = self.tls_connector()?;
 
         // we pass in the domain twice to check that the server's TLS
         // certificate is valid for the domain we're connecting to.
         let client = imap::connect((self.server.as_str(), port), &self.server, &tls)
-            .unwrap_or_else(|_| panic!("Couldn't connect to {}:{}", self.server, port));
-
-        // the client we have here is unauthenticated.
-        // to do anything useful with the e-mails, we need to log in
-        let imap_session = client
-            .login(&self.user, &self.password)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Couldn't securely connect to {}:{} for login {}",
-                    self.server, port, self.user
-                )
-            });
+            .inspect_err(|_| error!("Couldn't connect to {}:{}", self.server, port))?;
 
+        let imap_session = self.login(client, port)?;
         debug!(
             "Successfully connected to SECURE imap server {}",
             self.server
         );
-        Imap::Secured(imap_session)
+        Ok(Imap::Secured(imap_session))
+    }
+
+    fn start_starttls(&self, port: u16) -> Result<Imap> {
+        let tls = self.tls_connector()?;
+
+        // Connection starts in clear text, and is upgraded to TLS before login.
+        // Domain is used the same way than for secure connections.
+        let client = imap::connect_starttls((self.server.as_str(), port), &self.server, &tls)
+            .inspect_err(|_| error!("Couldn't connect to {}:{} using STARTTLS", self.server, port))?;
+
+        let imap_session = self.login(client, port)?;
+        debug!(
+            "Successfully connected to STARTTLS imap server {}",
+            self.server
+        );
+        Ok(Imap::Secured(imap_session))
+    }
+
+    fn start_insecure(&self, port: u16) -> Result<Imap> {
+        warn!("!!! Connecting to {}:{} WITHOUT ANY ENCRYPTION. Your password and all your messages will be readable by anyone on the network !!!",
+            self.server, port);
+        let stream = TcpStream::connect((self.server.as_str(), port))
+            .inspect_err(|_| error!("Couldn't connect to {}:{}", self.server, port))?;
+        let mut client = Client::new(stream);
+        client.read_greeting()?;
+
+        let imap_session = self.login(client, port)?;
+        debug!(
+            "Successfully connected to UNSECURE imap server {}",
+            self.server
+        );
+        Ok(Imap::Insecured(imap_session))
+    }
+
+    /// Authenticates the given client, whatever the transport it uses
+    fn login<T: Read + Write>(&self, client: Client<T>, port: u16) -> Result<Session<T>> {
+        // the client we have here is unauthenticated.
+        // to do anything useful with the e-mails, we need to log in
+        let session = match &self.auth {
+            Some(auth) => {
+                let mut auth = auth.clone();
+                if let Some(refresh_token) = self.secret("refresh_token") {
+                    auth.refresh_token = refresh_token;
+                }
+                if let Some(client_secret) = self.secret("client_secret") {
+                    auth.client_secret = Some(client_secret);
+                }
+                let token = auth.access_token()
+                    .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, e.to_string())))?;
+                let authenticator = auth.authenticator(&self.user, &self.server, port, &token);
+                client.authenticate(auth.mechanism_name(), &authenticator)
+            },
+            None => {
+                let password = self.resolve_password().map_err(Error::Io)?;
+                client.login(&self.user, &password)
+            },
+        };
+        session.map_err(|(e, _client)| {
+            error!(
+                "Couldn't connect to {}:{} for login {}",
+                self.server, port, self.user
+            );
+            e
+        })
     }
 }
 
@@ -161,29 +536,71 @@ pub struct Settings {
     */
     #[serde(default = "Email::default")]
     pub email: Email,
+    /// Other IMAP accounts, which feeds can select using their `account` config
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub accounts: BTreeMap<String, Email>,
+    /// SMTP server used by feeds delivered using SMTP
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub smtp: Option<Smtp>,
+    /// When set, read state of feeds is also kept in that IMAP folder (of default account),
+    /// allowing several machines to read the same feeds without writing entries twice
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub state_folder: Option<String>,
+    /// When set, IMAP quota of accounts is checked at the beginning of each run
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub quota: Option<QuotaThresholds>,
     #[serde(default = "Config::new")]
     pub config: Config,
 }
 
 impl Settings {
+    /// Get the account with the given name (or the default one when no name is given)
+    pub fn account(&self, name: &Option<String>) -> Option<&Email> {
+        match name {
+            Some(name) => self.accounts.get(name),
+            None => Some(&self.email),
+        }
+    }
+
+    /// Decrypts secrets of all accounts
+    pub fn unlock_secrets(&mut self) -> std::result::Result<(), UnusableSecrets> {
+        self.email.unlock_secrets()?;
+        for email in self.accounts.values_mut() {
+            email.unlock_secrets()?;
+        }
+        Ok(())
+    }
+
+    /// Keeps OAuth2 refresh tokens rotated by token endpoints of all accounts.
+    /// Returns true when config changed.
+    pub fn keep_rotated_tokens(&mut self) -> std::result::Result<bool, UnusableSecrets> {
+        let mut changed = self.email.keep_rotated_token()?;
+        for email in self.accounts.values_mut() {
+            changed |= email.keep_rotated_token()?;
+        }
+        Ok(changed)
+    }
+
     pub fn is_false(value: &bool) -> bool {
         !value
     }
+    pub fn is_true(value: &bool) -> bool {
+        *value
+    }
     pub fn default_false() -> bool {
         false
     }
-    /*
-        pub fn is_true(value: &bool) -> bool {
-            !!value
-        }
-        pub fn default_true() -> bool {
-            true
-        }
-    */
+    pub fn default_true() -> bool {
+        true
+    }
     pub fn default() -> Settings {
         Settings {
             do_not_save: false,
             email: Email::default(),
+            accounts: BTreeMap::new(),
+            smtp: None,
+            state_folder: None,
+            quota: None,
             config: Config::new(),
         }
     }
@@ -0,0 +1,89 @@
+use lettre::address::{Address, Envelope as SmtpEnvelope};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use tests_bin::unit_tests;
+
+use super::delivery::*;
+use super::secrets::Secrets;
+use super::settings::Secure;
+
+/// SMTP server config, used by feeds which `delivery` is `Smtp`.
+/// Messages then go through the server (and its filters) like any other mail.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Smtp {
+    /// smtp server we want to connect to
+    pub server: String,
+    /// secured connection state : `Yes` for implicit TLS (typically on port 465),
+    /// `StartTls` for submission (typically on port 587)
+    #[serde(default = "Smtp::default_secure")]
+    pub secure: Secure,
+    /// username used to authenticate, if server requires authentication
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub user: Option<String>,
+    /// password used to authenticate. Prefer storing it as `smtp_password` in email secrets
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub password: Option<String>,
+    /// envelope sender address. When not set, user is used
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub sender: Option<String>,
+}
+
+impl Smtp {
+    /// default secure port, used by serde
+    pub fn default_secure() -> Secure {
+        Secure::StartTls(587)
+    }
+}
+
+/// Sends messages to their recipient through an SMTP server
+#[unit_tests("smtp.rs")]
+pub struct SmtpDelivery {
+    transport: SmtpTransport,
+    sender: Option<Address>,
+}
+
+impl SmtpDelivery {
+    /// Creates the SMTP transport. Connection only happens when messages are sent
+    pub fn new(smtp: &Smtp, secrets: &Option<Secrets>) -> Result<SmtpDelivery, UndeliverableMessage> {
+        let builder = match smtp.secure {
+            Secure::Yes(port) => SmtpTransport::relay(&smtp.server).map(|b| b.port(port)),
+            Secure::StartTls(port) => SmtpTransport::starttls_relay(&smtp.server).map(|b| b.port(port)),
+            Secure::No(port) => {
+                warn!("!!! Sending messages to {}:{} WITHOUT ANY ENCRYPTION !!!", smtp.server, port);
+                Ok(SmtpTransport::builder_dangerous(&smtp.server).port(port))
+            },
+        }
+        .map_err(|e| UndeliverableMessage::Smtp { reason: e.to_string() })?;
+        let builder = match &smtp.user {
+            Some(user) => {
+                let password = secrets.as_ref()
+                    .and_then(|secrets| secrets.get("smtp_password"))
+                    .or(smtp.password.as_ref())
+                    .cloned()
+                    .unwrap_or_default();
+                builder.credentials(Credentials::new(user.to_owned(), password))
+            },
+            None => builder,
+        };
+        let sender = smtp.sender.as_ref().or(smtp.user.as_ref())
+            .map(|sender| sender.parse::<Mailbox>().map(|mailbox| mailbox.email))
+            .transpose()
+            .map_err(|e| UndeliverableMessage::Smtp { reason: format!("invalid sender: {}", e) })?;
+        Ok(SmtpDelivery { transport: builder.build(), sender })
+    }
+}
+
+impl Delivery for SmtpDelivery {
+    fn deliver(&self, envelope: &Envelope) -> Result<(), UndeliverableMessage> {
+        // Recipient may have a display name (like `Nicolas Delsaux <nicolas.delsaux@gmx.fr>`), only its address is used
+        let recipient = envelope.recipient.parse::<Mailbox>()
+            .map(|mailbox| mailbox.email)
+            .map_err(|e| UndeliverableMessage::Smtp { reason: format!("invalid recipient {}: {}", envelope.recipient, e) })?;
+        let smtp_envelope = SmtpEnvelope::new(self.sender.clone(), vec![recipient])
+            .map_err(|e| UndeliverableMessage::Smtp { reason: e.to_string() })?;
+        self.transport.send_raw(&smtp_envelope, envelope.content.as_bytes())
+            .map_err(|e| UndeliverableMessage::Smtp { reason: e.to_string() })?;
+        Ok(())
+    }
+}
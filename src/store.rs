@@ -4,14 +4,24 @@ use directories::ProjectDirs;
 use tests_bin::unit_tests;
 use std::path::{PathBuf, Path};
 
+use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
 
 
+use super::delivery::{Deliveries, Target};
+use super::dry_run::DryRunDelivery;
 use super::export;
 use super::feed::Feed;
+use super::message::Message;
+use super::folder_template;
+use super::imap_state;
 use super::import;
+use super::import::UnreadableOpml;
+use super::outbox::Outbox;
+use super::secrets::{Secrets, UnusableSecrets};
+use super::session_pool::{SessionPool, SessionPools};
 use super::settings::Settings;
 
 use rayon::prelude::*;
@@ -20,7 +30,89 @@ use custom_error::custom_error;
 
 custom_error!{pub UnusableStore
     IO{source:std::io::Error} = "input/output error",
-    JsonParseError{source:serde_json::Error} = "Can't parse JSON content of store"
+    JsonParseError{source:serde_json::Error} = "Can't parse JSON content of store",
+    SecretsError{source:UnusableSecrets} = "Can't decrypt secrets of store",
+    CantSerialize{reason:String} = "Can't serialize store to JSON due to {reason}",
+    CantWrite{path:String, source:std::io::Error} = "Unable to write file {path}",
+    NoFile{command:String} = "Can't {command} if no file is given",
+    NoFeed{index:u32} = "There is no feed {index}",
+    NoAccount{account:String} = "There is no account {account}",
+    OpmlError{source:UnreadableOpml} = "Can't import OPML file"
+}
+
+custom_error!{pub UnmovableFeed
+    NoFeed{index:u32} = "There is no feed {index}",
+    Template{folder:String} = "Folder {folder} is a template, messages in it can't be moved",
+    NoAccount{account:String} = "Account {account} doesn't exist",
+    Imap{source:imap::error::Error} = "IMAP server error"
+}
+
+custom_error!{pub FailedImapCommand
+    Outbox{path:String, source:std::io::Error} = "Unable to read outbox {path}",
+    NotFlushed{count:usize, path:String} = "{count} messages are still waiting in {path}",
+    NotPruned{folders:String} = "Unable to remove old messages from {folders}",
+    NoAccount{account:String, folder:String} = "Account {account} of folder {folder} doesn't exist"
+}
+
+/// What went wrong during a run
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// Number of feeds which were read
+    pub feeds: usize,
+    /// Feeds which couldn't be read (url and reason)
+    pub unreadable_feeds: Vec<(String, String)>,
+    /// Feeds having messages which couldn't be delivered (url and number of messages)
+    pub undelivered: Vec<(String, usize)>,
+    /// IMAP servers which couldn't be reached
+    pub unreachable_servers: Vec<String>,
+    /// Why messages waiting in outbox couldn't all be written at the beginning of run
+    pub unflushed: Option<FailedImapCommand>,
+    /// Number of messages put in outbox during run, as IMAP server couldn't receive them
+    pub queued: usize,
+    /// Number of messages refused by IMAP server (which are set aside in outbox)
+    pub refused: usize,
+}
+
+impl RunSummary {
+    /// Check if everything was read and delivered
+    pub fn is_success(&self) -> bool {
+        self.unreadable_feeds.is_empty() && self.undelivered.is_empty() && self.unreachable_servers.is_empty()
+            && self.unflushed.is_none() && self.queued == 0 && self.refused == 0
+    }
+
+    /// Check if run failed because of network : an IMAP server couldn't be reached (or receive messages), or no feed could be read
+    pub fn is_network_failure(&self) -> bool {
+        !self.unreachable_servers.is_empty()
+            || self.queued > 0
+            || matches!(self.unflushed, Some(FailedImapCommand::NotFlushed { .. }))
+            || (self.feeds > 0 && self.unreadable_feeds.len() == self.feeds)
+    }
+
+    /// Logs all failures of run
+    pub fn log(&self) {
+        for server in &self.unreachable_servers {
+            error!("IMAP server {} couldn't be reached", server);
+        }
+        for (url, reason) in &self.unreadable_feeds {
+            error!("Feed {} couldn't be read: {}", url, reason);
+        }
+        for (url, count) in &self.undelivered {
+            error!("{} messages of feed {} couldn't be delivered. They will be read again on next run", count, url);
+        }
+        if let Some(unflushed) = &self.unflushed {
+            error!("{}", unflushed);
+        }
+        if self.queued > 0 {
+            error!("{} messages have been put in outbox. They will be written on next run", self.queued);
+        }
+        if self.refused > 0 {
+            error!("{} messages have been refused by IMAP server. They're set aside in outbox", self.refused);
+        }
+        if !self.is_success() {
+            error!("{} feeds out of {} couldn't be read, and {} messages couldn't be delivered",
+                self.unreadable_feeds.len(), self.feeds, self.undelivered.iter().map(|(_, count)| count).sum::<usize>());
+        }
+    }
 }
 
 #[unit_tests("store.rs")]
@@ -85,58 +177,148 @@ impl Store {
         }
     }
 
+    /// Decrypts secrets of all accounts.
+    /// Only commands connecting to a server, or changing secrets, need them (and the key to decrypt them).
+    pub fn unlock_secrets(&mut self) -> Result<(), UnusableStore> {
+        Ok(self.settings.unlock_secrets()?)
+    }
+
     /// Save all informations in the store file
-    fn save(&self) {
+    fn save(&self) -> Result<(), UnusableStore> {
         info!("Saving config file {}", self.path.to_string_lossy());
-        let serialized = serde_json::to_string_pretty(self).expect("Can't serialize Store to JSON");
+        let serialized = serde_json::to_string_pretty(self)
+            .map_err(|e| UnusableStore::CantSerialize { reason: e.to_string() })?;
         let directory = self.path.parent().unwrap_or(Path::new("."));
         fs::create_dir_all(directory)
-            .unwrap_or_else(|_| panic!("Unable to create directory for file {}", self.path.to_string_lossy()));
-        fs::write(&self.path, serialized)
-            .unwrap_or_else(|_| panic!("Unable to write file {}", self.path.to_string_lossy()));
+            .and_then(|_| fs::write(&self.path, serialized))
+            .map_err(|source| UnusableStore::CantWrite { path: self.path.to_string_lossy().to_string(), source })
+    }
+
+    /// Save the store if it has been modified (and saving is allowed), and report the error when it can't be saved.
+    /// Store is then no more saved when dropped.
+    pub fn close(mut self) -> Result<(), UnusableStore> {
+        // Refresh tokens rotated by OAuth2 providers replace the configured ones, which may no more be valid
+        if self.settings.keep_rotated_tokens()? {
+            self.dirty = true;
+        }
+        let result = if !self.dirty {
+            Ok(())
+        } else if self.settings.do_not_save {
+            error!("do_not_save flag is set in config.json. NOT SAVING {} !", self.path.to_string_lossy());
+            Ok(())
+        } else {
+            info!("store has been modified. Saving {} !", self.path.to_string_lossy());
+            self.save()
+        };
+        // A failure has already been reported, so store is not saved again when dropped
+        self.dirty = false;
+        result
     }
 
     /// Create a new configuration file with the given email.
-    pub fn init_config(&mut self, email: String) {
+    pub fn init_config(&mut self, email: String) -> Result<(), UnusableStore> {
         if self.path.exists() {
             warn!("Config file {} already exists, leaving it unchanged.", self.path.to_string_lossy());
+            Ok(())
         } else {
             println!("Config file {} created, please edit it to finish configuration.", self.path.to_string_lossy());
             self.settings.config.email = Some(email);
             self.dirty = true;
-            self.save();
+            self.save()
         }
     }
 
     /// Set a new value for email and save file (prior to obviously exiting)
-    pub fn set_email(&mut self, email: String) {
+    pub fn set_email(&mut self, email: String) -> Result<(), UnusableStore> {
         self.settings.config.email = Some(email);
         self.dirty = true;
-        self.save();
+        self.save()
+    }
+
+    /// Stores the given secret encrypted in config file.
+    /// When value is not given, it is read from the first line of standard input
+    /// (to not have it in shell history).
+    pub fn set_secret(&mut self, account: Option<String>, name: String, value: Option<String>) -> Result<(), UnusableStore> {
+        // Other secrets are encrypted again with the new one
+        self.unlock_secrets()?;
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line)?;
+                line.trim_end_matches(&['\r', '\n'][..]).to_owned()
+            }
+        };
+        let email = match &account {
+            Some(account) => self.settings.accounts.get_mut(account)
+                .ok_or_else(|| UnusableStore::NoAccount { account: account.to_owned() })?,
+            None => &mut self.settings.email,
+        };
+        let secrets = email.secrets.get_or_insert_with(Secrets::default);
+        secrets.set(&name, &value)?;
+        if name == "password" && !email.password.is_empty() {
+            info!("Removing clear text password from {}", self.path.to_string_lossy());
+            email.password.clear();
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Encrypts again all secrets (of all accounts) with a new key (the given identity file, or the passphrase given in
+    /// `RRSS2IMAP_NEW_PASSPHRASE` environment variable).
+    /// Secrets which can be encrypted again are, even when others can't (in which case the last error is returned).
+    pub fn rotate_secrets(&mut self, identity_file: Option<PathBuf>) -> Result<(), UnusableStore> {
+        self.unlock_secrets()?;
+        let all_secrets = std::iter::once(&mut self.settings.email)
+            .chain(self.settings.accounts.values_mut())
+            .filter_map(|email| email.secrets.as_mut());
+        let mut rotated = 0;
+        let mut result = Ok(());
+        for secrets in all_secrets {
+            match secrets.rotate(identity_file.clone()) {
+                Ok(_) => {
+                    info!("Secrets {:?} have been encrypted with the new key", secrets.names());
+                    rotated += 1;
+                },
+                Err(e) => {
+                    error!("Unable to rotate secrets {:?} due to {}", secrets.names(), e);
+                    result = Err(UnusableStore::SecretsError { source: e });
+                },
+            }
+        }
+        if rotated == 0 {
+            warn!("No secret has been encrypted again in {}", self.path.to_string_lossy());
+        } else {
+            self.dirty = true;
+        }
+        result
     }
 
     /// Exports config into an OPML file
     /// see [export](rrss2imap::export::export) for implementation details
-    pub fn export(&self, file: Option<PathBuf>) {
-        let path_to_write = file.expect("Can't export file if no file is given");
+    pub fn export(&self, file: Option<PathBuf>) -> Result<(), UnusableStore> {
+        let path_to_write = file.ok_or_else(|| UnusableStore::NoFile { command: "export".to_owned() })?;
         warn!("exporting content to {:?}", path_to_write);
-        export::export(&path_to_write, self);
+        export::export(&path_to_write, self)
+            .map_err(|source| UnusableStore::CantWrite { path: path_to_write.to_string_lossy().to_string(), source })?;
         info!("exported feeds to {:?}", path_to_write);
+        Ok(())
     }
 
     /// Import rss feeds provided as an opml file
     /// see [import](rrss2imap::import::import) for implementation details
-    pub fn import(&mut self, file: Option<PathBuf>) {
-        let path_to_read = file.expect("Can't import file if no file is given");
+    pub fn import(&mut self, file: Option<PathBuf>) -> Result<(), UnusableStore> {
+        let path_to_read = file.ok_or_else(|| UnusableStore::NoFile { command: "import".to_owned() })?;
         info!("importing content from {:?}", path_to_read);
         let count = self.feeds.len();
-        import::import(&path_to_read, self);
+        import::import(&path_to_read, self)?;
         self.dirty = true;
         info!(
             "imported {} feeds from {:?}",
             self.feeds.len() - count,
             path_to_read
         );
+        Ok(())
     }
 
     /// Add a feed to the feeds list and immediatly save the store.
@@ -153,10 +335,40 @@ impl Store {
 
     /// Delete the feed which id is given as parameter.
     /// The use of a number is a compatibility requirement
-    pub fn delete(&mut self, feed: u32) {
+    pub fn delete(&mut self, feed: u32) -> Result<(), UnusableStore> {
+        if feed as usize >= self.feeds.len() {
+            return Err(UnusableStore::NoFeed { index: feed });
+        }
         let f = self.feeds.remove(feed as usize);
         self.dirty = true;
         info!("Removed {:?}", f);
+        Ok(())
+    }
+
+    /// Changes the folder of the feed which id is given as parameter, and moves messages already written for that feed
+    /// (the ones having its url in their `X-RSS-Feed-Url` header) from previous folder to the new one.
+    /// Folder is only changed once messages have been moved.
+    pub fn move_feed(&mut self, feed: u32, folder: String) -> Result<(), UnmovableFeed> {
+        let moved = self.feeds.get(feed as usize).ok_or(UnmovableFeed::NoFeed { index: feed })?;
+        let previous = moved.config.get_folder(&self.settings.config);
+        if folder_template::is_template(&previous) {
+            return Err(UnmovableFeed::Template { folder: previous });
+        }
+        if moved.config.get_delivery(&self.settings.config) == Target::Imap {
+            let account = moved.config.get_account(&self.settings.config);
+            let pools = SessionPools::new(&self.settings);
+            let pool = pools.get(&account)
+                .ok_or_else(|| UnmovableFeed::NoAccount { account: format!("{:?}", account) })?;
+            let result = pool.move_feed_messages(&previous, &folder, &moved.url);
+            pools.logout();
+            let count = result?;
+            info!("Moved {} messages of {} from {} to {}", count, moved.url, previous, folder);
+        } else {
+            warn!("Feed {} is not written to IMAP, its messages are not moved", moved.url);
+        }
+        self.feeds[feed as usize].config.folder = Some(folder);
+        self.dirty = true;
+        Ok(())
     }
 
     /// Reset the config file by removing all feeds and config
@@ -167,18 +379,287 @@ impl Store {
         info!("store has been cleared to contain only {:?}", self);
     }
 
+    /// Write all messages waiting in outbox to IMAP server
+    pub fn flush(&self) -> Result<(), FailedImapCommand> {
+        let pools = SessionPools::new(&self.settings);
+        let result = self.flush_outbox(&Outbox::next_to(&self.path), &pools);
+        pools.logout();
+        result
+    }
+
+    fn flush_outbox(&self, outbox: &Outbox, pools: &SessionPools) -> Result<(), FailedImapCommand> {
+        let path = outbox.path.to_string_lossy().to_string();
+        match outbox.flush(pools) {
+            Ok((_, 0)) => Ok(()),
+            Ok((_, remaining)) => Err(FailedImapCommand::NotFlushed { count: remaining, path }),
+            Err(source) => Err(FailedImapCommand::Outbox { path, source }),
+        }
+    }
+
     /// Run all rss to imap transformation
-    /// Each feed is read and immediatly written in this thread.
-    /// This should be rewritten to allow optimization/parallelism
-    pub fn run(&mut self) {
-        self.dirty = true;
+    /// Feeds are read and delivered in parallel, all sharing the same pools of IMAP sessions (one per account)
+    /// (which are all closed once every feed has been processed).
+    /// Messages waiting in outbox are written before any feed is read.
+    /// When a dry run output directory is given, messages are written there as .eml files,
+    /// and neither IMAP nor the store are modified.
+    /// Returned summary lists feeds which couldn't be read, and messages which couldn't be delivered.
+    pub fn run(&mut self, dry_run: Option<PathBuf>) -> RunSummary {
         let feeds_length = self.feeds.len();
+        let mut summary = RunSummary { feeds: feeds_length, ..RunSummary::default() };
         // Initialize mail server before processing feeds
-        self.feeds = self.feeds
+        let pools = SessionPools::new(&self.settings);
+        let outbox = Outbox::next_to(&self.path);
+        // State shared with other machines replaces the local one (but a dry run never touches IMAP, so it uses the local one)
+        let mut stored_state = None;
+        if let (Some(folder), None) = (&self.settings.state_folder, &dry_run) {
+            let pool = pools.get(&None).expect("default account always has a session pool");
+            match pool.read_state(folder) {
+                Ok(stored) => {
+                    info!("Feeds state read from {}", folder);
+                    stored.state.apply(&mut self.feeds);
+                    stored_state = Some(stored);
+                },
+                Err(e) => {
+                    error!("Unable to read feeds state from {} due to {}. Feeds won't be read, to not write entries twice", folder, e);
+                    summary.unreachable_servers.push(pool.email().server.clone());
+                    pools.logout();
+                    return summary;
+                }
+            }
+        }
+        // A dry run never touches IMAP, so quota is not checked
+        let (without_inlined_images, full) = match dry_run {
+            Some(_) => (HashSet::new(), HashSet::new()),
+            None => self.check_quotas(&pools),
+        };
+        if !self.settings.do_not_save && dry_run.is_none() {
+            for (account, pool) in pools.iter() {
+                let mut folders: Vec<String> = self.feeds.iter()
+                    .filter(|feed| feed.config.get_delivery(&self.settings.config) == Target::Imap)
+                    .filter(|feed| &feed.config.get_account(&self.settings.config) == account)
+                    .map(|feed| feed.config.get_folder(&self.settings.config))
+                    // Folders built from templates are only known once messages are read
+                    .filter(|folder| !folder_template::is_template(folder))
+                    .collect();
+                folders.sort();
+                folders.dedup();
+                if !folders.is_empty() {
+                    if let Err(e) = pool.ensure_folders(&folders) {
+                        error!("Unable to create missing folders on {} due to {}", pool.email().server, e);
+                        if SessionPool::is_connection_broken(&e) {
+                            summary.unreachable_servers.push(pool.email().server.clone());
+                        }
+                    }
+                }
+            }
+            summary.unflushed = self.flush_outbox(&outbox, &pools).err();
+        }
+        let mut deliveries = Deliveries::new(&self.settings, &self.feeds, &pools, &outbox);
+        deliveries.dry_run = dry_run.as_ref().map(|directory| DryRunDelivery::new(directory));
+        deliveries.without_inlined_images = without_inlined_images;
+        let (feeds, failures): (Vec<Feed>, Vec<Result<usize, String>>) = self.feeds
             .par_iter().enumerate()
-            .map(|element| (element.1, element.1.read(element.0, &feeds_length, )))
-            .map(|(feed, messages)| feed.write_new_messages(&self.settings, messages))
-            .collect::<Vec<Feed>>();
+            .map(|element| if self.writes_to(element.1, &full) {
+                let account = element.1.config.get_account(&self.settings.config);
+                (element.1.clone(), Err(format!("quota of IMAP account {:?} is nearly exhausted", account)))
+            } else {
+                match element.1.read(element.0, &feeds_length) {
+                    Ok(messages) => {
+                        let (feed, undelivered) = element.1.write_new_messages(&self.settings, &deliveries, messages);
+                        (feed, Ok(undelivered))
+                    },
+                    // An unreadable feed is left unchanged, to be read again on next run
+                    Err(e) => (element.1.clone(), Err(e.to_string())),
+                }
+            })
+            .unzip();
+        summary.queued = outbox.enqueued();
+        summary.refused = outbox.refused();
+        for (feed, failure) in feeds.iter().zip(failures) {
+            match failure {
+                Ok(0) => {},
+                Ok(undelivered) => summary.undelivered.push((feed.url.clone(), undelivered)),
+                Err(reason) => summary.unreadable_feeds.push((feed.url.clone(), reason)),
+            }
+        }
+        if let Some(dry_run) = &deliveries.dry_run {
+            match dry_run.write_index() {
+                Ok(index) => info!("Dry run done. Written messages are listed in {}", index.to_string_lossy()),
+                Err(e) => error!("Unable to write dry run index due to {}", e),
+            }
+            return summary;
+        }
+        self.dirty = true;
+        self.feeds = feeds;
+        if let (Some(folder), Some(stored)) = (&self.settings.state_folder, stored_state) {
+            if !self.settings.do_not_save {
+                let pool = pools.get(&None).expect("default account always has a session pool");
+                if let Err(e) = imap_state::write_feeds_state(pool, folder, &mut self.feeds, stored.uid) {
+                    error!("Unable to write feeds state in {} due to {}", folder, e);
+                    summary.unreachable_servers.push(pool.email().server.clone());
+                }
+            }
+        }
+        if !self.settings.do_not_save {
+            if let Err(e) = self.prune_folders(&pools) {
+                error!("{}", e);
+            }
+        }
+        // Sessions are kept until state has been written and folders pruned, to not log in again
+        pools.logout();
+        summary
+    }
+
+    /// Rebuilds the read state of feeds from what is already in their IMAP folders (useful when `config.json` was lost).
+    /// Each feed is read, and its entries are searched (using their `X-RSS-Entry-Id` header) in the folder they would be written to.
+    /// The most recent entry found becomes the last read one, unless feed state is already more recent.
+    pub fn resync(&mut self) -> RunSummary {
+        let feeds_length = self.feeds.len();
+        let mut summary = RunSummary { feeds: feeds_length, ..RunSummary::default() };
+        let pools = SessionPools::new(&self.settings);
+        let resynced: Vec<(Feed, Result<(), String>)> = self.feeds
+            .par_iter().enumerate()
+            .map(|(index, feed)| {
+                if feed.config.get_delivery(&self.settings.config) != Target::Imap {
+                    info!("Feed {} is not written to IMAP, it can't be resynced", feed.url);
+                    return (feed.clone(), Ok(()));
+                }
+                match feed.read(index, &feeds_length) {
+                    Ok(messages) => match self.resync_feed(feed, &messages, &pools) {
+                        Ok(resynced) => (resynced, Ok(())),
+                        Err(e) => (feed.clone(), Err(e)),
+                    },
+                    Err(e) => (feed.clone(), Err(e.to_string())),
+                }
+            })
+            .collect();
+        pools.logout();
+        let mut feeds = vec![];
+        for (feed, result) in resynced {
+            if let Err(reason) = result {
+                summary.unreadable_feeds.push((feed.url.clone(), reason));
+            }
+            feeds.push(feed);
+        }
+        self.feeds = feeds;
+        self.dirty = true;
+        summary
+    }
+
+    /// Finds the most recent entry of feed already written in IMAP, and makes it the last read one
+    fn resync_feed(&self, feed: &Feed, messages: &[Message], pools: &SessionPools) -> Result<Feed, String> {
+        let account = feed.config.get_account(&self.settings.config);
+        let pool = pools.get(&account)
+            .ok_or_else(|| format!("account {:?} doesn't exist", account))?;
+        let mut resynced = feed.clone();
+        // Feeds are supposed to put the latest emitted message in first position
+        for message in messages {
+            let folder = message.folder(feed, &self.settings);
+            let written = pool.contains_entry(&folder, &message.id)
+                .map_err(|e| format!("unable to search {} due to {}", folder, e))?;
+            if written {
+                if feed.last_message.is_none() || message.last_date >= feed.last_updated {
+                    info!("Feed {} has been written up to entry {} (in {})", feed.url, message.title, folder);
+                    resynced.last_updated = message.last_date;
+                    resynced.last_message = Some(message.id.clone());
+                } else {
+                    info!("Feed {} state is more recent than its last written entry {}", feed.url, message.title);
+                }
+                return Ok(resynced);
+            }
+        }
+        warn!("No entry of feed {} has been found in IMAP. Its state is unchanged", feed.url);
+        Ok(resynced)
+    }
+
+    /// Check if the given feed is written to one of the given IMAP accounts
+    fn writes_to(&self, feed: &Feed, accounts: &HashSet<Option<String>>) -> bool {
+        feed.config.get_delivery(&self.settings.config) == Target::Imap
+            && accounts.contains(&feed.config.get_account(&self.settings.config))
+    }
+
+    /// Reads quota of all IMAP accounts used by feeds, when quota thresholds are set.
+    /// Returns the accounts for which images should no more be inlined, and the ones which should no more be written to.
+    fn check_quotas(&self, pools: &SessionPools) -> (HashSet<Option<String>>, HashSet<Option<String>>) {
+        let mut without_inlined_images = HashSet::new();
+        let mut full = HashSet::new();
+        let thresholds = match &self.settings.quota {
+            Some(thresholds) => thresholds,
+            None => return (without_inlined_images, full),
+        };
+        for (account, pool) in pools.iter() {
+            let used = self.feeds.iter().any(|feed| self.writes_to(feed, &HashSet::from([account.clone()])));
+            if !used {
+                continue;
+            }
+            match pool.quota() {
+                Ok(Some(usage)) => {
+                    info!("{} uses {:.0}% of its quota {:?}", pool.email().server, usage.percent(), usage.root);
+                    if thresholds.stops(&usage) {
+                        error!("Quota of {} is nearly exhausted ({:.0}%). Its feeds won't be read", pool.email().server, usage.percent());
+                        full.insert(account.clone());
+                    } else if thresholds.warns(&usage) {
+                        warn!("Quota of {} is {:.0}% used ({} KB out of {} KB)", pool.email().server, usage.percent(), usage.used, usage.limit);
+                    }
+                    if thresholds.disables_inlining(&usage) {
+                        warn!("Images won't be inlined in messages written to {}", pool.email().server);
+                        without_inlined_images.insert(account.clone());
+                    }
+                },
+                Ok(None) => debug!("{} has no quota", pool.email().server),
+                Err(e) => warn!("Unable to read quota of {} due to {}", pool.email().server, e),
+            }
+        }
+        (without_inlined_images, full)
+    }
+
+    /// Removes expired messages from the IMAP folders of all feeds having a retention
+    pub fn prune(&self) -> Result<(), FailedImapCommand> {
+        let pools = SessionPools::new(&self.settings);
+        let result = self.prune_folders(&pools);
+        pools.logout();
+        result
+    }
+
+    /// Removes expired messages from all folders, even when some of them fail
+    /// (failures are then reported once all folders have been pruned)
+    fn prune_folders(&self, pools: &SessionPools) -> Result<(), FailedImapCommand> {
+        let mut pruned: Vec<(Option<String>, String)> = vec![];
+        let mut failed: Vec<String> = vec![];
+        for feed in self.feeds.iter() {
+            if feed.config.get_delivery(&self.settings.config) != Target::Imap {
+                continue;
+            }
+            if let Some(retention) = feed.config.get_retention(&self.settings.config) {
+                let account = feed.config.get_account(&self.settings.config);
+                let folder = feed.config.get_folder(&self.settings.config);
+                if folder_template::is_template(&folder) {
+                    warn!("Folder {} of feed {} is a template. Its messages are not removed", folder, feed.url);
+                    continue;
+                }
+                // Folders shared by feeds are pruned only once
+                let key = (account, folder);
+                if pruned.contains(&key) {
+                    continue;
+                }
+                let (account, folder) = &key;
+                match pools.get(account).map(|pool| pool.prune(folder, &retention)) {
+                    Some(Ok(0)) => debug!("No message to remove from {}", folder),
+                    Some(Ok(count)) => info!("Removed {} messages from {}", count, folder),
+                    Some(Err(e)) => {
+                        error!("Unable to remove old messages from {} due to {}", folder, e);
+                        failed.push(folder.clone());
+                    },
+                    None => return Err(FailedImapCommand::NoAccount { account: format!("{:?}", account), folder: folder.clone() }),
+                }
+                pruned.push(key);
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(FailedImapCommand::NotPruned { folders: failed.join(", ") })
+        }
     }
 
     /// Prints all the feeds to stdout.
@@ -217,7 +698,9 @@ impl Drop for Store {
                 error!("do_not_save flag is set in config.json. NOT SAVING {} !", self.path.to_string_lossy())
             } else {
                 info!("store has been modified. Saving {} !", self.path.to_string_lossy());
-                self.save();
+                if let Err(e) = self.save() {
+                    error!("Unable to save {} due to {}", self.path.to_string_lossy(), e);
+                }
             }
         }
     }
@@ -0,0 +1,81 @@
+use base64::alphabet::Alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::{DecodePaddingMode, Engine};
+use tests_bin::unit_tests;
+
+lazy_static! {
+    /// Modified base64 of RFC 3501 uses `,` instead of `/`, and has no padding
+    static ref MODIFIED_BASE64: GeneralPurpose = GeneralPurpose::new(
+        &Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,").unwrap(),
+        GeneralPurposeConfig::new()
+            .with_encode_padding(false)
+            .with_decode_padding_mode(DecodePaddingMode::RequireNone));
+}
+
+/// Characters which are written as is in modified UTF-7
+fn is_printable(c: char) -> bool {
+    (' '..='~').contains(&c)
+}
+
+/// Encodes the given mailbox name in IMAP modified UTF-7 (see RFC 3501 §5.1.3).
+/// Printable ASCII characters are kept as is (excepted `&`, which becomes `&-`),
+/// and other ones are written as modified base64 of their UTF-16 representation, between `&` and `-`.
+#[unit_tests("utf7.rs")]
+pub fn encode(name: &str) -> String {
+    let mut encoded = String::new();
+    let mut pending: Vec<u16> = vec![];
+    for c in name.chars() {
+        if is_printable(c) {
+            flush(&mut encoded, &mut pending);
+            if c == '&' {
+                encoded.push_str("&-");
+            } else {
+                encoded.push(c);
+            }
+        } else {
+            let mut buffer = [0u16; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut buffer));
+        }
+    }
+    flush(&mut encoded, &mut pending);
+    encoded
+}
+
+/// Writes the pending UTF-16 characters as a modified base64 section
+fn flush(encoded: &mut String, pending: &mut Vec<u16>) {
+    if !pending.is_empty() {
+        let bytes: Vec<u8> = pending.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+        encoded.push('&');
+        encoded.push_str(&MODIFIED_BASE64.encode(bytes));
+        encoded.push('-');
+        pending.clear();
+    }
+}
+
+/// Decodes the given IMAP modified UTF-7 mailbox name.
+/// Returns None when name is not valid modified UTF-7.
+pub fn decode(name: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut remaining = name;
+    while let Some(start) = remaining.find('&') {
+        decoded.push_str(&remaining[..start]);
+        let section = &remaining[start + 1..];
+        let end = section.find('-')?;
+        if end == 0 {
+            decoded.push('&');
+        } else {
+            let bytes = MODIFIED_BASE64.decode(&section[..end]).ok()?;
+            if bytes.len() % 2 != 0 {
+                return None;
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            decoded.push_str(&String::from_utf16(&units).ok()?);
+        }
+        remaining = &section[end + 1..];
+    }
+    decoded.push_str(remaining);
+    Some(decoded)
+}
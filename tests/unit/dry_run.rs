@@ -0,0 +1,23 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::{envelope, temp_dir};
+
+fn titled<'a>(message_id: &'a str, title: &'a str, folder: &'a str) -> Envelope<'a> {
+	Envelope { message_id, title, ..envelope(folder) }
+}
+
+#[test]
+fn can_write_messages_and_index() {
+	let root = temp_dir("can_write_messages_and_index");
+	let dry_run = DryRunDelivery::new(&root);
+	dry_run.deliver(&titled("0123abcd@rrss2imap", "Comics <3", "News/Comics")).unwrap();
+	dry_run.deliver(&titled("4567ef01@rrss2imap", "Without folder", "")).unwrap();
+	assert_that!(fs::read_to_string(root.join("News/Comics/0123abcd.eml")).unwrap())
+		.contains("Subject: An entry");
+	assert_that!(root.join("4567ef01.eml").exists()).is_true();
+	let index = fs::read_to_string(dry_run.write_index().unwrap()).unwrap();
+	assert_that!(index).contains("<a href=\"News/Comics/0123abcd.eml\">Comics &lt;3</a>");
+	assert_that!(index).contains("<a href=\"4567ef01.eml\">Without folder</a>");
+}
@@ -0,0 +1,32 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::entry;
+
+#[test]
+fn unchanged_entry_is_not_updated() {
+	let state = EntryState::of(&entry("content", 14), None);
+	assert_that!(state.has_changed(&entry("content", 14))).is_false();
+	// Only date changed, which happens with RSS items having no date
+	assert_that!(state.has_changed(&Message { dated: false, ..entry("content", 15) })).is_false();
+}
+
+#[test]
+fn entry_with_newer_date_is_updated() {
+	let state = EntryState::of(&entry("content", 14), None);
+	assert_that!(state.has_changed(&entry("content", 15))).is_true();
+}
+
+#[test]
+fn changed_entry_is_updated() {
+	let state = EntryState::of(&entry("content", 14), None);
+	assert_that!(state.has_changed(&entry("corrected content", 14))).is_true();
+	assert_that!(state.has_changed(&entry("corrected content", 15))).is_true();
+}
+
+#[test]
+fn older_version_of_entry_is_not_updated() {
+	let state = EntryState::of(&entry("content", 14), None);
+	assert_that!(state.has_changed(&entry("previous content", 13))).is_false();
+}
@@ -1,6 +1,7 @@
 extern crate spectral;
 use spectral::prelude::*;
 use super::*;
+use crate::fixtures::{deliveries, entry, temp_dir};
 
 #[test]
 fn can_build_feed_from_just_a_url() {
@@ -13,10 +14,17 @@ fn can_build_feed_from_just_a_url() {
 				email: None,
 				folder: None,
 				from: None,
-				inline_image_as_data: false
+				inline_image_as_data: false,
+				flags: None,
+				delivery: None,
+				retention: None,
+				account: None,
+				track_updates: false,
+				mark_updates: false
 			},
 			last_updated: Feed::at_epoch(),
-			last_message: None
+			last_message: None,
+			entries: BTreeMap::new()
 		})
 }
 
@@ -31,10 +39,17 @@ fn can_build_feed_from_url_and_email() {
 				email: Some("a@example.com".to_string()),
 				folder: None,
 				from: None,
-				inline_image_as_data: false
+				inline_image_as_data: false,
+				flags: None,
+				delivery: None,
+				retention: None,
+				account: None,
+				track_updates: false,
+				mark_updates: false
 			},
 			last_updated: Feed::at_epoch(),
-			last_message: None
+			last_message: None,
+			entries: BTreeMap::new()
 		})
 }
 
@@ -49,10 +64,17 @@ fn can_build_feed_from_url_and_folder() {
 				email: None,
 				folder: Some("folder".to_string()),
 				from: None,
-				inline_image_as_data: false
+				inline_image_as_data: false,
+				flags: None,
+				delivery: None,
+				retention: None,
+				account: None,
+				track_updates: false,
+				mark_updates: false
 			},
 			last_updated: Feed::at_epoch(),
-			last_message: None
+			last_message: None,
+			entries: BTreeMap::new()
 		})
 }
 
@@ -67,10 +89,17 @@ fn can_build_feed_from_url_email_and_folder() {
 				email: None,
 				folder: Some("folder".to_string()),
 				from: None,
-				inline_image_as_data: false
+				inline_image_as_data: false,
+				flags: None,
+				delivery: None,
+				retention: None,
+				account: None,
+				track_updates: false,
+				mark_updates: false
 			},
 			last_updated: Feed::at_epoch(),
-			last_message: None
+			last_message: None,
+			entries: BTreeMap::new()
 		})
 }
 
@@ -78,7 +107,7 @@ fn can_build_feed_from_url_email_and_folder() {
 #[test]
 fn can_read_an_atom_feed() {
 	let feed = Feed::from_vec(vec!["a@b.c".to_string()]);
-	let messages = feed.read_response_text(include_str!("example.atom").to_string());
+	let messages = feed.read_response_text(include_str!("example.atom").to_string()).unwrap();
 	assert_that!(messages)
 		.has_length(1)
 		;
@@ -92,11 +121,67 @@ fn can_read_an_atom_feed() {
 #[test]
 fn can_read_a_rss_feed() {
 	let feed = Feed::from_vec(vec!["a@b.c".to_string()]);
-	let messages = feed.read_response_text(include_str!("example.rss").to_string());
+	let messages = feed.read_response_text(include_str!("example.rss").to_string()).unwrap();
 	assert_that!(messages)
 		.has_length(1)
 		;
 	let first = &messages[0];
 	assert_that!(first.content).is_equal_to("Here is some text containing an interesting description.".to_string());
 
-}
\ No newline at end of file
+}
+#[test]
+fn cant_read_a_web_page() {
+	let feed = Feed::from_vec(vec!["https://example.com".to_string()]);
+	let messages = feed.read_response_text("<html><body>Not a feed</body></html>".to_string());
+	assert_that!(messages.is_err()).is_true();
+}
+
+#[test]
+fn can_write_changed_entries_again() {
+	let root = temp_dir("can_write_changed_entries_again");
+	let deliveries = deliveries(Some(&root));
+	let settings = Settings::default();
+	let mut feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	feed.config.track_updates = true;
+	let (feed, undelivered) = feed.write_new_messages(&settings, &deliveries, vec![entry("First version", 14)]);
+	assert_that!(undelivered).is_equal_to(0);
+	assert_that!(feed.entries.len()).is_equal_to(1);
+	// Unchanged entry is not written again
+	let (feed, _) = feed.write_new_messages(&settings, &deliveries, vec![entry("First version", 14)]);
+	assert_that!(std::fs::read_dir(&root).unwrap().count()).is_equal_to(1);
+	// But changed one is
+	let (feed, undelivered) = feed.write_new_messages(&settings, &deliveries, vec![entry("Corrected version", 14)]);
+	assert_that!(undelivered).is_equal_to(0);
+	assert_that!(std::fs::read_dir(&root).unwrap().count()).is_equal_to(2);
+	assert_that!(feed.entries["https://example.com/entry"].hash)
+		.is_equal_to(entry("Corrected version", 14).content_hash());
+}
+
+#[test]
+fn can_forget_entries_no_more_in_feed() {
+	let mut feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	feed.config.track_updates = true;
+	feed.entries.insert("https://example.com/old".to_string(), EntryState::of(&entry("Old", 14), None));
+	feed.last_message = Some("https://example.com/entry".to_string());
+	let deliveries = deliveries(None);
+	// Entry was read before updates were tracked : its state is only recorded
+	let (feed, undelivered) = feed.write_new_messages(&Settings::default(), &deliveries, vec![entry("Already read", 14)]);
+	assert_that!(undelivered).is_equal_to(0);
+	assert_that!(feed.entries.keys().cloned().collect::<Vec<String>>())
+		.is_equal_to(vec!["https://example.com/entry".to_string()]);
+}
+
+#[test]
+fn can_keep_previous_version_when_new_one_cant_be_located() {
+	let root = temp_dir("can_keep_previous_version_when_new_one_cant_be_located");
+	let deliveries = deliveries(Some(&root));
+	let previous = crate::delivery::Location { folder: "RSS".to_string(), uid_validity: 1, uid: 42 };
+	let mut feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	feed.config.track_updates = true;
+	feed.last_message = Some("https://example.com/entry".to_string());
+	feed.entries.insert("https://example.com/entry".to_string(), EntryState::of(&entry("First version", 14), Some(previous.clone())));
+	// Dry run can't locate written messages, so previous version must still be known, to be removed by next update
+	let (feed, undelivered) = feed.write_new_messages(&Settings::default(), &deliveries, vec![entry("Corrected version", 14)]);
+	assert_that!(undelivered).is_equal_to(0);
+	assert_that!(feed.entries["https://example.com/entry"].location).is_equal_to(Some(previous));
+}
@@ -0,0 +1,152 @@
+//! Fixtures shared by unit tests
+use chrono::NaiveDate;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::delivery::{Deliveries, Envelope};
+use crate::dry_run::DryRunDelivery;
+use crate::message::Message;
+use crate::settings::{Email, Secure};
+
+/// Empty directory (in system temp dir) dedicated to the given test
+pub fn temp_dir(test: &str) -> PathBuf {
+	let mut root = env::temp_dir();
+	root.push(format!("rrss2imap_{}", test));
+	let _ = fs::remove_dir_all(&root);
+	root
+}
+
+/// Envelope of a small message to write in the given folder
+pub fn envelope(folder: &str) -> Envelope<'_> {
+	Envelope {
+		message_id: "entry@rrss2imap",
+		title: "An entry",
+		folder,
+		recipient: "me@example.com",
+		content: "Subject: An entry\r\n\r\nEntry content\r\n",
+		flags: &[],
+		date: Some(NaiveDate::from_ymd_opt(2023, 4, 2).unwrap().and_hms_opt(8, 5, 0).unwrap()),
+	}
+}
+
+/// Deliveries without any configured server, writing messages in the given dry run directory (if any)
+pub fn deliveries(dry_run: Option<&Path>) -> Deliveries<'static> {
+	Deliveries {
+		imap: HashMap::new(),
+		smtp: None,
+		maildirs: HashMap::new(),
+		mboxes: HashMap::new(),
+		dry_run: dry_run.map(DryRunDelivery::new),
+		without_inlined_images: HashSet::new(),
+	}
+}
+
+/// Message read from the entry having the given id
+pub fn message(id: &str) -> Message {
+	Message {
+		authors: vec![],
+		content: "content".to_string(),
+		id: id.to_string(),
+		last_date: NaiveDate::from_ymd_opt(2024, 3, 14).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+		dated: true,
+		links: vec![],
+		title: "title".to_string(),
+		feed_title: "Feed title".to_string(),
+		categories: vec![],
+		revision: None,
+	}
+}
+
+/// Version of entry `https://example.com/entry` having the given content, as published the given day of March 2024
+pub fn entry(content: &str, day: u32) -> Message {
+	Message {
+		content: content.to_string(),
+		last_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+		..message("https://example.com/entry")
+	}
+}
+
+/// Commands received by a fake IMAP server (without their tags, and without message content for APPEND)
+pub type Received = Arc<Mutex<Vec<String>>>;
+
+/// Starts a fake IMAP server on a local port, and returns the email config connecting to it (without retry delay).
+/// Each command gets the untagged lines and the status returned for it by `respond`
+/// (LOGIN and LOGOUT are always accepted). A `CLOSE` status makes the server drop connection without answering.
+pub fn imap_server<F>(respond: F) -> (Email, Received)
+where
+	F: Fn(&str) -> (String, String) + Send + Sync + 'static,
+{
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let received: Received = Arc::new(Mutex::new(vec![]));
+	let respond = Arc::new(respond);
+	let server_received = received.clone();
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let received = server_received.clone();
+			let respond = respond.clone();
+			thread::spawn(move || serve(stream.unwrap(), &received, respond.as_ref()));
+		}
+	});
+	let email = Email {
+		server: "127.0.0.1".to_string(),
+		secure: Secure::No(port),
+		retry_delay: 0,
+		..Email::default()
+	};
+	(email, received)
+}
+
+fn serve(stream: TcpStream, received: &Received, respond: &dyn Fn(&str) -> (String, String)) {
+	let mut writer = stream.try_clone().unwrap();
+	let mut reader = BufReader::new(stream);
+	writer.write_all(b"* OK IMAP4rev1 fake server ready\r\n").unwrap();
+	let mut line = String::new();
+	while reader.read_line(&mut line).unwrap_or(0) > 0 {
+		let (tag, command) = line.trim_end().split_once(' ').unwrap_or((line.trim_end(), ""));
+		let (tag, command) = (tag.to_owned(), command.to_owned());
+		line.clear();
+		// Literals (only used by APPEND) are read, but not recorded
+		if let Some(length) = command.strip_suffix('}').and_then(|c| c.rsplit_once('{')).map(|(_, l)| l.parse::<usize>().unwrap()) {
+			writer.write_all(b"+ Ready for literal data\r\n").unwrap();
+			let mut literal = vec![0; length + 2];
+			reader.read_exact(&mut literal).unwrap();
+		}
+		received.lock().unwrap().push(command.clone());
+		let (untagged, status) = match command.split(' ').next().unwrap_or("").to_uppercase().as_str() {
+			"LOGIN" => (String::new(), "OK LOGIN completed".to_string()),
+			"LOGOUT" => ("* BYE\r\n".to_string(), "OK LOGOUT completed".to_string()),
+			_ => respond(&command),
+		};
+		if status == "CLOSE" {
+			return;
+		}
+		if writer.write_all(format!("{}{} {}\r\n", untagged, tag, status).as_bytes()).is_err() {
+			return;
+		}
+	}
+}
+
+/// Successful response to the given command (selected mailboxes have the UIDVALIDITY 1, and are empty)
+pub fn imap_ok(command: &str) -> (String, String) {
+	if command.to_uppercase().starts_with("SELECT ") {
+		("* FLAGS (\\Seen \\Deleted)\r\n* 0 EXISTS\r\n* 0 RECENT\r\n* OK [UIDVALIDITY 1] UIDs valid\r\n".to_string(),
+			"OK [READ-WRITE] SELECT completed".to_string())
+	} else {
+		(String::new(), "OK completed".to_string())
+	}
+}
+
+/// Commands starting with the given words (like `UID COPY`) received by a fake IMAP server
+pub fn received(received: &Received, kind: &str) -> Vec<String> {
+	received.lock().unwrap().iter()
+		.filter(|command| format!("{} ", command.to_uppercase()).starts_with(&format!("{} ", kind.to_uppercase())))
+		.cloned()
+		.collect()
+}
@@ -0,0 +1,54 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+
+fn values() -> FolderValues {
+	FolderValues {
+		feed_title: "xkcd.com".to_string(),
+		feed_host: "xkcd.com".to_string(),
+		year: "2024".to_string(),
+		month: "03".to_string(),
+		category: "Comics".to_string(),
+	}
+}
+
+#[test]
+fn can_detect_templates() {
+	assert_that!(is_template("RSS/{feed_title}")).is_true();
+	assert_that!(is_template("RSS/{year}/{month}")).is_true();
+	assert_that!(is_template("RSS/Comics")).is_false();
+	assert_that!(is_template("RSS/{unknown}")).is_false();
+}
+
+#[test]
+fn can_resolve_all_placeholders() {
+	assert_that!(resolve("RSS/{feed_title}/{category}", &values()))
+		.is_equal_to("RSS/xkcd-com/Comics".to_string());
+	assert_that!(resolve("Archives/{feed_host}/{year}-{month}", &values()))
+		.is_equal_to("Archives/xkcd-com/2024-03".to_string());
+}
+
+#[test]
+fn cant_create_sub_folders_from_values() {
+	let mut values = values();
+	values.feed_title = " AC/DC news ".to_string();
+	assert_that!(resolve("RSS/{feed_title}", &values))
+		.is_equal_to("RSS/AC-DC news".to_string());
+}
+
+#[test]
+fn cant_create_sub_folders_on_servers_using_dot_delimiter() {
+	let mut values = values();
+	values.feed_title = "Mr. Robot".to_string();
+	assert_that!(resolve("INBOX.News.{feed_host}.{feed_title}", &values))
+		.is_equal_to("INBOX.News.xkcd-com.Mr- Robot".to_string());
+}
+
+#[test]
+fn cant_resolve_placeholders_in_values() {
+	let mut values = values();
+	values.feed_title = "{year} in review".to_string();
+	assert_that!(resolve("RSS/{feed_title}/{month}", &values))
+		.is_equal_to("RSS/{year} in review/03".to_string());
+}
@@ -0,0 +1,41 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use chrono::NaiveDate;
+use super::*;
+
+fn feed(url: &str, day: u32) -> Feed {
+	let mut feed = Feed::from_vec(vec![url.to_string()]);
+	feed.last_updated = NaiveDate::from_ymd_opt(2024, 3, day).unwrap().and_hms_opt(0, 0, 0).unwrap();
+	feed.last_message = Some(format!("{}/entry-{}", url, day));
+	feed
+}
+
+#[test]
+fn can_apply_state_to_feeds() {
+	let state = State::of(&[feed("https://example.com/feed.xml", 14)]);
+	let mut feeds = vec![feed("https://example.com/feed.xml", 1), feed("https://example.org/feed.xml", 2)];
+	state.apply(&mut feeds);
+	assert_that!(feeds[0]).is_equal_to(feed("https://example.com/feed.xml", 14));
+	// Feeds unknown in state are left unchanged
+	assert_that!(feeds[1]).is_equal_to(feed("https://example.org/feed.xml", 2));
+}
+
+#[test]
+fn merge_keeps_most_recent_state_of_each_feed() {
+	let mut local = State::of(&[feed("https://example.com/feed.xml", 14), feed("https://example.org/feed.xml", 2)]);
+	let remote = State::of(&[feed("https://example.com/feed.xml", 10), feed("https://example.org/feed.xml", 5),
+		feed("https://example.net/feed.xml", 1)]);
+	local.merge(&remote);
+	assert_that!(local).is_equal_to(State::of(&[feed("https://example.com/feed.xml", 14),
+		feed("https://example.org/feed.xml", 5), feed("https://example.net/feed.xml", 1)]));
+}
+
+#[test]
+fn can_read_state_from_its_message() {
+	let state = State::of(&[feed("https://example.com/feed.xml", 14)]);
+	let message = state.to_message().unwrap();
+	assert_that!(message).contains("X-RSS2IMAP-State: 1\r\n");
+	let body = message.split("\r\n\r\n").nth(1).unwrap();
+	assert_that!(State::from_body(body.as_bytes()).unwrap()).is_equal_to(state);
+}
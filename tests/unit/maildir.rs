@@ -0,0 +1,56 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::{envelope, temp_dir};
+
+fn files_in(directory: PathBuf) -> Vec<PathBuf> {
+	fs::read_dir(directory).unwrap()
+		.map(|entry| entry.unwrap().path())
+		.collect()
+}
+
+#[test]
+fn can_map_folders_to_maildir_subfolders() {
+	let maildir = MaildirDelivery::new(Path::new("/mail"));
+	assert_that!(maildir.folder_path("")).is_equal_to(PathBuf::from("/mail"));
+	assert_that!(maildir.folder_path("INBOX")).is_equal_to(PathBuf::from("/mail"));
+	assert_that!(maildir.folder_path("News")).is_equal_to(PathBuf::from("/mail/.News"));
+	assert_that!(maildir.folder_path("News/Comics")).is_equal_to(PathBuf::from("/mail/.News.Comics"));
+	assert_that!(maildir.folder_path("News.Comics")).is_equal_to(PathBuf::from("/mail/.News.Comics"));
+}
+
+#[test]
+fn can_encode_flags_in_info() {
+	assert_that!(MaildirDelivery::info(&[])).is_none();
+	assert_that!(MaildirDelivery::info(&["$Important".to_string()])).is_none();
+	assert_that!(MaildirDelivery::info(&["\\Seen".to_string(), "\\Flagged".to_string(), "$Important".to_string()]))
+		.is_some()
+		.is_equal_to(":2,FS".to_string());
+}
+
+#[test]
+fn can_write_message_in_new() {
+	let root = temp_dir("can_write_message_in_new");
+	let maildir = MaildirDelivery::new(&root);
+	maildir.deliver(&envelope("News/Comics")).unwrap();
+	let folder = root.join(".News.Comics");
+	assert_that!(folder.join("maildirfolder").exists()).is_true();
+	assert_that!(files_in(folder.join("tmp"))).is_empty();
+	let delivered = files_in(folder.join("new"));
+	assert_that!(delivered).has_length(1);
+	assert_that!(fs::read_to_string(&delivered[0]).unwrap()).contains("Subject: An entry");
+}
+
+#[test]
+fn can_write_flagged_message_in_cur() {
+	let root = temp_dir("can_write_flagged_message_in_cur");
+	let maildir = MaildirDelivery::new(&root);
+	let flags = ["\\Seen".to_string()];
+	maildir.deliver(&Envelope { flags: &flags, ..envelope("") }).unwrap();
+	assert_that!(root.join("maildirfolder").exists()).is_false();
+	assert_that!(files_in(root.join("new"))).is_empty();
+	let delivered = files_in(root.join("cur"));
+	assert_that!(delivered).has_length(1);
+	assert_that!(delivered[0].to_string_lossy().ends_with(":2,S")).is_true();
+}
@@ -0,0 +1,38 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::{envelope, temp_dir};
+
+fn envelope_with<'a>(folder: &'a str, content: &'a str) -> Envelope<'a> {
+	Envelope { content, ..envelope(folder) }
+}
+
+#[test]
+fn can_map_folders_to_mbox_files() {
+	let mbox = MboxDelivery::new(Path::new("/mail"));
+	assert_that!(mbox.file_path("")).is_equal_to(PathBuf::from("/mail/INBOX.mbox"));
+	assert_that!(mbox.file_path("News")).is_equal_to(PathBuf::from("/mail/News.mbox"));
+	assert_that!(mbox.file_path("News/Comics")).is_equal_to(PathBuf::from("/mail/News/Comics.mbox"));
+}
+
+#[test]
+fn can_escape_from_lines() {
+	let text = MboxDelivery::format(&envelope_with("", "Subject: An entry\r\n\r\nFrom here\r\n>From there\r\nFromage\r\n"));
+	assert_that!(text).is_equal_to(
+		"From rrss2imap Sun Apr  2 08:05:00 2023\nSubject: An entry\n\n>From here\n>>From there\nFromage\n\n".to_string());
+}
+
+#[test]
+fn can_append_messages_to_mbox() {
+	let root = temp_dir("can_append_messages_to_mbox");
+	let mbox = MboxDelivery::new(&root);
+	mbox.deliver(&envelope_with("archive", "Subject: first\r\n\r\nfirst\r\n")).unwrap();
+	mbox.deliver(&envelope_with("archive", "Subject: second\r\n\r\nsecond\r\n")).unwrap();
+	let file = root.join("archive.mbox");
+	let text = fs::read_to_string(&file).unwrap();
+	assert_that!(text.matches("\nFrom rrss2imap ").count()).is_equal_to(1);
+	assert_that!(text).starts_with("From rrss2imap ");
+	assert_that!(text).contains("Subject: second");
+	assert_that!(root.join("archive.mbox.lock").exists()).is_false();
+}
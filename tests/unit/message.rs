@@ -0,0 +1,86 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::message;
+
+#[test]
+fn can_generate_stable_message_id() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	assert_that!(message("entry").message_id(&feed))
+		.is_equal_to(message("entry").message_id(&feed));
+	assert_that!(message("entry").message_id(&feed))
+		.ends_with("@rrss2imap");
+}
+
+#[test]
+fn can_generate_distinct_message_ids() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	let other_feed = Feed::from_vec(vec!["https://example.org/feed.xml".to_string()]);
+	assert_that!(message("entry").message_id(&feed))
+		.is_not_equal_to(message("other entry").message_id(&feed));
+	assert_that!(message("entry").message_id(&feed))
+		.is_not_equal_to(message("entry").message_id(&other_feed));
+}
+
+#[test]
+fn can_write_entry_headers() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	let built = message("https://example.com/entry").build_message(&feed, &Settings::default()).unwrap();
+	assert_that!(built)
+		.contains(format!("Message-ID: <{}>", message("https://example.com/entry").message_id(&feed)).as_str());
+	assert_that!(built)
+		.contains("X-RSS-Entry-Id: https://example.com/entry");
+}
+
+#[test]
+fn can_resolve_folder_template() {
+	let mut feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	feed.config.folder = Some("RSS/{feed_title}/{feed_host}/{year}/{category}".to_string());
+	let mut message = message("entry");
+	message.categories = vec!["News".to_string()];
+	assert_that!(message.folder(&feed, &Settings::default()))
+		.is_equal_to("RSS/Feed title/example-com/2024/News".to_string());
+}
+
+#[test]
+fn can_use_feed_host_when_feed_has_no_title() {
+	let mut feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	feed.config.folder = Some("RSS/{feed_title}/{category}".to_string());
+	let mut message = message("entry");
+	message.feed_title = "".to_string();
+	assert_that!(message.folder(&feed, &Settings::default()))
+		.is_equal_to("RSS/example-com/Uncategorized".to_string());
+}
+
+#[test]
+fn can_generate_distinct_message_ids_for_revisions() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	let mut revised = message("entry");
+	revised.revision = Some(revised.content_hash());
+	assert_that!(revised.message_id(&feed))
+		.is_not_equal_to(message("entry").message_id(&feed));
+}
+
+#[test]
+fn can_identify_feed_of_message() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	let built = message("entry").build_message(&feed, &Settings::default()).unwrap();
+	assert_that!(built).contains("X-RSS-Feed-Url: https://example.com/feed.xml");
+	assert_that!(built).contains("X-RSS-Entry-Id: entry");
+}
+
+#[test]
+fn cant_inject_headers_with_entry_id() {
+	let feed = Feed::from_vec(vec!["https://example.com/feed.xml".to_string()]);
+	let built = message("entry\r\nBcc: victim@example.com").build_message(&feed, &Settings::default()).unwrap();
+	assert_that!(built).contains("X-RSS-Entry-Id: entry  Bcc: victim@example.com\r\n");
+	let headers = built.split("\r\n\r\n").next().unwrap();
+	assert_that!(headers.lines().any(|line| line.starts_with("Bcc:"))).is_false();
+}
+
+#[test]
+fn can_retry_messages_whose_images_couldnt_be_inlined() {
+	assert_that!(UnprocessableMessage::CantWriteTransformedMessage.is_temporary()).is_true();
+	assert_that!(UnprocessableMessage::CantPutDateInMessage { value: "never".to_string() }.is_temporary()).is_false();
+}
@@ -0,0 +1,102 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use super::*;
+
+fn oauth(mechanism: Mechanism, token_endpoint: String) -> OAuth {
+	OAuth {
+		mechanism,
+		token_endpoint,
+		client_id: "client".to_string(),
+		client_secret: None,
+		refresh_token: "refresh".to_string(),
+		scope: None,
+		tokens: TokenCache::default(),
+	}
+}
+
+#[test]
+fn can_build_xoauth2_response() {
+	let authenticator = oauth(Mechanism::XOAuth2, "".to_string())
+		.authenticator("user@example.com", "imap.example.com", 993, "token");
+	assert_that!(authenticator.process(b""))
+		.is_equal_to("user=user@example.com\x01auth=Bearer token\x01\x01".to_string());
+}
+
+#[test]
+fn can_build_oauthbearer_response() {
+	let authenticator = oauth(Mechanism::OAuthBearer, "".to_string())
+		.authenticator("user@example.com", "imap.example.com", 993, "token");
+	assert_that!(authenticator.process(b""))
+		.is_equal_to("n,a=user@example.com,\x01host=imap.example.com\x01port=993\x01auth=Bearer token\x01\x01".to_string());
+	assert_that!(authenticator.process(b"{\"status\":\"invalid_token\"}"))
+		.is_equal_to("\x01".to_string());
+}
+
+/// Starts a minimal token endpoint giving the given answers (one per connection), and returning the received request bodies
+fn token_endpoint(answers: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<String>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let endpoint = format!("http://{}/token", listener.local_addr().unwrap());
+	let server = thread::spawn(move || {
+		answers.iter().map(|answer| {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut content_length = 0;
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.to_lowercase().starts_with("content-length:") {
+					content_length = line[15..].trim().parse().unwrap();
+				}
+				if line.trim().is_empty() {
+					break;
+				}
+			}
+			let mut body = vec![0; content_length];
+			reader.read_exact(&mut body).unwrap();
+			write!(reader.get_mut(), "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+				answer.len(), answer).unwrap();
+			String::from_utf8(body).unwrap()
+		}).collect()
+	});
+	(endpoint, server)
+}
+
+#[test]
+fn can_get_access_token_from_local_endpoint() {
+	let (endpoint, server) = token_endpoint(vec!["{\"access_token\":\"fresh token\",\"token_type\":\"Bearer\",\"expires_in\":3600}"]);
+	assert_that!(oauth(Mechanism::XOAuth2, endpoint).access_token())
+		.is_ok_containing("fresh token".to_string());
+	assert_that!(server.join().unwrap()[0])
+		.contains("grant_type=refresh_token");
+}
+
+#[test]
+fn can_reuse_access_token_until_it_expires() {
+	// Endpoint only answers once : the second token must come from cache
+	let (endpoint, server) = token_endpoint(vec!["{\"access_token\":\"fresh token\",\"token_type\":\"Bearer\",\"expires_in\":3600}"]);
+	let auth = oauth(Mechanism::XOAuth2, endpoint);
+	assert_that!(auth.access_token()).is_ok_containing("fresh token".to_string());
+	assert_that!(auth.clone().access_token()).is_ok_containing("fresh token".to_string());
+	assert_that!(server.join().unwrap()).has_length(1);
+}
+
+#[test]
+fn can_use_rotated_refresh_token() {
+	// Without expires_in, access token is not reused
+	let (endpoint, server) = token_endpoint(vec![
+		"{\"access_token\":\"first\",\"token_type\":\"Bearer\",\"refresh_token\":\"rotated\"}",
+		"{\"access_token\":\"second\",\"token_type\":\"Bearer\"}",
+	]);
+	let auth = oauth(Mechanism::XOAuth2, endpoint);
+	assert_that!(auth.rotated_refresh_token()).is_none();
+	assert_that!(auth.access_token()).is_ok_containing("first".to_string());
+	assert_that!(auth.rotated_refresh_token()).is_some().is_equal_to("rotated".to_string());
+	assert_that!(auth.access_token()).is_ok_containing("second".to_string());
+	let requests = server.join().unwrap();
+	assert_that!(requests[0]).contains("refresh_token=refresh&");
+	assert_that!(requests[1]).contains("refresh_token=rotated&");
+}
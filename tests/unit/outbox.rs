@@ -0,0 +1,66 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use std::fs;
+use super::*;
+use crate::fixtures::{imap_ok, imap_server, received, temp_dir};
+
+#[test]
+fn can_queue_messages_in_order() {
+	let mut store_path = temp_dir("can_queue_messages_in_order");
+	store_path.push("config.json");
+	let outbox = Outbox::next_to(&store_path);
+	outbox.enqueue(&QueuedMessage { folder: "first".to_string(), content: "first content".to_string(), flags: vec![], date: None, account: None }).unwrap();
+	outbox.enqueue(&QueuedMessage { folder: "second".to_string(), content: "second content".to_string(), flags: vec![], date: None, account: None }).unwrap();
+	let queued = outbox.queued().unwrap();
+	assert_that!(queued)
+		.has_length(2);
+	let first: QueuedMessage = serde_json::from_str(&fs::read_to_string(&queued[0]).unwrap()).unwrap();
+	assert_that!(first.folder).is_equal_to("first".to_string());
+	assert_that!(first.content).is_equal_to("first content".to_string());
+}
+
+#[test]
+fn can_read_missing_outbox() {
+	let mut store_path = temp_dir("can_read_missing_outbox");
+	store_path.push("config.json");
+	assert_that!(Outbox::next_to(&store_path).queued().unwrap())
+		.is_empty();
+}
+
+#[test]
+fn can_skip_unreadable_messages() {
+	let mut store_path = temp_dir("can_skip_unreadable_messages");
+	store_path.push("config.json");
+	let outbox = Outbox::next_to(&store_path);
+	fs::create_dir_all(&outbox.path).unwrap();
+	fs::write(outbox.path.join("000000000000000-000000.json"), "{\"folder\": \"trunc").unwrap();
+	outbox.enqueue(&QueuedMessage { folder: "kept".to_string(), content: "content".to_string(), flags: vec![], date: None, account: Some("unknown".to_string()) }).unwrap();
+	let settings = crate::settings::Settings::default();
+	let pools = SessionPools::new(&settings);
+	// Message for an unknown account is left in outbox, and the truncated one is moved aside
+	assert_that!(outbox.flush(&pools).unwrap()).is_equal_to((0, 1));
+	assert_that!(outbox.path.join("000000000000000-000000.bad")).is_a_file();
+	assert_that!(outbox.queued().unwrap()).has_length(1);
+}
+
+#[test]
+fn can_set_aside_messages_refused_by_server() {
+	let mut store_path = temp_dir("can_set_aside_messages_refused_by_server");
+	store_path.push("config.json");
+	let outbox = Outbox::next_to(&store_path);
+	outbox.enqueue(&QueuedMessage { folder: "refused".to_string(), content: "too big".to_string(), flags: vec![], date: None, account: None }).unwrap();
+	outbox.enqueue(&QueuedMessage { folder: "accepted".to_string(), content: "content".to_string(), flags: vec![], date: None, account: None }).unwrap();
+	let (email, commands) = imap_server(|command| if command.starts_with("APPEND \"refused\"") {
+		(String::new(), "NO [TOOBIG] Message too big".to_string())
+	} else {
+		imap_ok(command)
+	});
+	let settings = crate::settings::Settings { email, ..crate::settings::Settings::default() };
+	let pools = SessionPools::new(&settings);
+	// Refused message doesn't prevent next one from being written
+	assert_that!(outbox.flush(&pools).unwrap()).is_equal_to((1, 0));
+	assert_that!(received(&commands, "APPEND").iter().any(|c| c.starts_with("APPEND \"accepted\""))).is_true();
+	assert_that!(outbox.queued().unwrap()).is_empty();
+	assert_that!(fs::read_dir(&outbox.path).unwrap().count()).is_equal_to(1);
+}
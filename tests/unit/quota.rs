@@ -0,0 +1,88 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use crate::quota::{parse_quota, parse_quota_root, quote, QuotaThresholds, Usage};
+
+fn usage(used: u64, limit: u64) -> Usage {
+	Usage {
+		root: "".to_string(),
+		used,
+		limit,
+	}
+}
+
+#[test]
+fn can_read_empty_quota_root() {
+	let response = b"* QUOTAROOT INBOX \"\"\r\n* QUOTA \"\" (STORAGE 10 512)\r\n";
+	assert_that!(parse_quota_root(response)).is_some().is_equal_to("".to_string());
+}
+
+#[test]
+fn can_read_named_quota_root() {
+	let response = b"* QUOTAROOT INBOX \"User quota\"\r\n";
+	assert_that!(parse_quota_root(response)).is_some().is_equal_to("User quota".to_string());
+}
+
+#[test]
+fn has_no_quota_root_when_server_defines_none() {
+	assert_that!(parse_quota_root(b"* QUOTAROOT INBOX\r\n")).is_none();
+}
+
+#[test]
+fn can_read_storage_usage() {
+	let response = b"* QUOTA \"User quota\" (MESSAGE 3 1000 STORAGE 10 512)\r\n";
+	assert_that!(parse_quota(response)).is_some().is_equal_to(Usage {
+		root: "User quota".to_string(),
+		used: 10,
+		limit: 512,
+	});
+}
+
+#[test]
+fn has_no_usage_without_storage_resource() {
+	assert_that!(parse_quota(b"* QUOTA \"\" (MESSAGE 3 1000)\r\n")).is_none();
+}
+
+#[test]
+fn can_quote_root() {
+	assert_that!(quote("a \"b\"")).is_equal_to("\"a \\\"b\\\"\"".to_string());
+}
+
+#[test]
+fn can_compute_percent() {
+	assert_that!(usage(256, 512).percent()).is_equal_to(50.0);
+}
+
+#[test]
+fn has_no_threshold_reached_without_limit() {
+	let thresholds = QuotaThresholds {
+		warn: 80,
+		no_inline: Some(90),
+		stop: Some(98),
+	};
+	assert_that!(usage(0, 0).percent()).is_equal_to(0.0);
+	assert_that!(thresholds.warns(&usage(12, 0))).is_false();
+	assert_that!(thresholds.stops(&usage(12, 0))).is_false();
+}
+
+#[test]
+fn only_warns_by_default() {
+	let thresholds: QuotaThresholds = serde_json::from_str("{}").unwrap();
+	assert_that!(thresholds.warn).is_equal_to(80);
+	assert_that!(thresholds.warns(&usage(85, 100))).is_true();
+	assert_that!(thresholds.warns(&usage(50, 100))).is_false();
+	assert_that!(thresholds.disables_inlining(&usage(99, 100))).is_false();
+	assert_that!(thresholds.stops(&usage(99, 100))).is_false();
+}
+
+#[test]
+fn can_disable_inlining_then_stop() {
+	let thresholds = QuotaThresholds {
+		warn: 80,
+		no_inline: Some(90),
+		stop: Some(98),
+	};
+	assert_that!(thresholds.disables_inlining(&usage(92, 100))).is_true();
+	assert_that!(thresholds.stops(&usage(92, 100))).is_false();
+	assert_that!(thresholds.stops(&usage(98, 100))).is_true();
+}
@@ -0,0 +1,72 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+
+fn uids(values: &[Uid]) -> HashSet<Uid> {
+	values.iter().cloned().collect()
+}
+
+fn retention(max_age_days: Option<u32>, max_count: Option<usize>) -> Retention {
+	Retention { max_age_days, max_count, trash: None }
+}
+
+#[test]
+fn can_build_too_old_query() {
+	let today = NaiveDate::from_ymd_opt(2023, 3, 10).unwrap();
+	assert_that!(retention(None, Some(10)).too_old_query(today)).is_none();
+	assert_that!(retention(Some(30), None).too_old_query(today))
+		.is_some()
+		.is_equal_to("UNFLAGGED HEADER X-RSS-Entry-Id \"\" BEFORE 8-Feb-2023".to_string());
+}
+
+#[test]
+fn can_keep_only_most_recent_messages() {
+	// Message 2 is flagged, so it is kept, even if in excess
+	let expired = retention(None, Some(2)).expired(&uids(&[1, 2, 3, 4, 5]), &uids(&[1, 3, 4, 5]), &uids(&[]));
+	assert_that!(expired).is_equal_to(vec![1, 3]);
+}
+
+#[test]
+fn can_remove_too_old_messages() {
+	let expired = retention(Some(30), None).expired(&uids(&[1, 2, 3, 4]), &uids(&[1, 2, 3, 4]), &uids(&[1, 2]));
+	assert_that!(expired).is_equal_to(vec![1, 2]);
+}
+
+#[test]
+fn can_combine_age_and_count() {
+	let expired = retention(Some(30), Some(3)).expired(&uids(&[1, 2, 3, 4, 5]), &uids(&[1, 3, 4, 5]), &uids(&[1]));
+	assert_that!(expired).is_equal_to(vec![1]);
+	let expired = retention(Some(30), Some(2)).expired(&uids(&[1, 2, 3, 4, 5]), &uids(&[1, 3, 4, 5]), &uids(&[1, 4]));
+	assert_that!(expired).is_equal_to(vec![1, 3, 4]);
+}
+
+#[test]
+fn can_format_uid_set() {
+	assert_that!(uid_set(&[1, 3, 12])).is_equal_to("1,3,12".to_string());
+}
+
+#[test]
+fn can_escape_header_query() {
+	assert_that!(header_query(ENTRY_HEADER, "a \"quoted\" \\ id"))
+		.is_equal_to("HEADER X-RSS-Entry-Id \"a \\\"quoted\\\" \\\\ id\"".to_string());
+}
+
+#[test]
+fn can_read_exact_header_value() {
+	let fields = b"X-RSS-Feed-Url: https://a.com/feed2\r\n\r\n";
+	assert_that!(header_value(fields, FEED_HEADER)).is_some().is_equal_to("https://a.com/feed2".to_string());
+	assert_that!(header_value(fields, FEED_HEADER).as_deref() == Some("https://a.com/feed")).is_false();
+	assert_that!(header_value(fields, ENTRY_HEADER)).is_none();
+}
+
+#[test]
+fn can_read_folded_header_value() {
+	let fields = b"x-rss-entry-id: a title\r\n\t with spaces\r\n\r\n";
+	assert_that!(header_value(fields, ENTRY_HEADER)).is_some().is_equal_to("a title with spaces".to_string());
+}
+
+#[test]
+fn can_make_header_safe() {
+	assert_that!(header_safe(" entry\r\nBcc: victim@example.com\t")).is_equal_to("entry  Bcc: victim@example.com".to_string());
+}
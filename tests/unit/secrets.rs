@@ -0,0 +1,71 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use age::secrecy::ExposeSecret;
+use std::env;
+use std::fs;
+use super::*;
+
+fn identity_file(name: &str) -> PathBuf {
+	let mut file = env::temp_dir();
+	file.push(name);
+	fs::write(&file, age::x25519::Identity::generate().to_string().expose_secret()).unwrap();
+	file
+}
+
+#[test]
+fn can_unlock_secrets_with_identity_file() {
+	let mut secrets = Secrets {
+		identity_file: Some(identity_file("rrss2imap_can_unlock_secrets_with_identity_file.txt")),
+		..Secrets::default()
+	};
+	secrets.set("password", "my password").unwrap();
+	assert_that!(secrets.encrypted.contains("my password")).is_false();
+	let mut read: Secrets = serde_json::from_str(&serde_json::to_string(&secrets).unwrap()).unwrap();
+	assert_that!(read.get("password")).is_none();
+	read.unlock().unwrap();
+	assert_that!(read.get("password")).is_some().is_equal_to(&"my password".to_string());
+}
+
+#[test]
+fn can_rotate_secrets_to_another_identity_file() {
+	let mut secrets = Secrets {
+		identity_file: Some(identity_file("rrss2imap_can_rotate_secrets_first.txt")),
+		..Secrets::default()
+	};
+	secrets.set("refresh_token", "my token").unwrap();
+	let previous = secrets.clone();
+	secrets.rotate(Some(identity_file("rrss2imap_can_rotate_secrets_second.txt"))).unwrap();
+	// Previous key can't be used anymore
+	let mut old_key = Secrets {
+		identity_file: previous.identity_file,
+		..secrets.clone()
+	};
+	assert_that!(old_key.unlock()).is_err();
+	let mut read = secrets.clone();
+	read.unlock().unwrap();
+	assert_that!(read.get("refresh_token")).is_some().is_equal_to(&"my token".to_string());
+}
+
+#[test]
+fn can_use_passphrase_from_environment() {
+	env::remove_var(PASSPHRASE);
+	let mut secrets = Secrets::default();
+	assert_that!(secrets.set("password", "my password")).is_err();
+	env::set_var(PASSPHRASE, "first passphrase");
+	secrets.set("password", "my password").unwrap();
+	env::set_var(NEW_PASSPHRASE, "second passphrase");
+	secrets.rotate(None).unwrap();
+	let mut read = secrets.clone();
+	assert_that!(read.unlock()).is_err();
+	env::set_var(PASSPHRASE, "second passphrase");
+	read.unlock().unwrap();
+	assert_that!(read.get("password")).is_some().is_equal_to(&"my password".to_string());
+}
+
+#[test]
+fn can_unlock_empty_secrets() {
+	let mut secrets = Secrets::default();
+	assert_that!(secrets.unlock()).is_ok();
+	assert_that!(secrets.names()).is_empty();
+}
@@ -0,0 +1,201 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use super::*;
+use crate::fixtures::{imap_ok, imap_server, received};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn can_reuse_session_between_operations() {
+	let (email, commands) = imap_server(imap_ok);
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_ok();
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_ok();
+	pool.logout();
+	assert_that!(received(&commands, "LOGIN")).has_length(1);
+	assert_that!(received(&commands, "SELECT")).has_length(2);
+	assert_that!(received(&commands, "LOGOUT")).has_length(1);
+}
+
+#[test]
+fn can_reconnect_when_server_closed_session() {
+	let selected = AtomicUsize::new(0);
+	let (email, commands) = imap_server(move |command| if selected.fetch_add(1, Ordering::SeqCst) == 0 {
+		(String::new(), "CLOSE".to_string())
+	} else {
+		imap_ok(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_ok();
+	// Broken session has been dropped, and a new one opened
+	assert_that!(received(&commands, "LOGIN")).has_length(2);
+	assert_that!(received(&commands, "SELECT")).has_length(2);
+}
+
+#[test]
+fn can_retry_refused_command_with_same_session() {
+	let selected = AtomicUsize::new(0);
+	let (email, commands) = imap_server(move |command| if selected.fetch_add(1, Ordering::SeqCst) == 0 {
+		(String::new(), "NO try again".to_string())
+	} else {
+		imap_ok(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_ok();
+	assert_that!(received(&commands, "LOGIN")).has_length(1);
+	assert_that!(received(&commands, "SELECT")).has_length(2);
+}
+
+#[test]
+fn can_give_up_after_configured_retries() {
+	let (mut email, commands) = imap_server(|_| (String::new(), "NO never".to_string()));
+	email.retry_max_count = 2;
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_err();
+	assert_that!(received(&commands, "SELECT")).has_length(3);
+}
+
+#[test]
+fn can_fail_immediately_once_server_is_unreachable() {
+	let (mut email, commands) = imap_server(|_| (String::new(), "CLOSE".to_string()));
+	email.retry_max_count = 1;
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_err();
+	assert_that!(pool.with_session(|imap| imap.select("INBOX"))).is_err();
+	// Only the attempts of the first operation reached server
+	assert_that!(received(&commands, "LOGIN")).has_length(2);
+}
+
+#[test]
+fn missing_folder_contains_no_message() {
+	let (email, commands) = imap_server(|command| if command.starts_with("SELECT") {
+		(String::new(), "NO Mailbox doesn't exist".to_string())
+	} else {
+		imap_ok(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.contains_message("News/2024", "entry@rrss2imap")).is_ok_containing(false);
+	// Missing folder is not an error, so it is not retried
+	assert_that!(received(&commands, "SELECT")).has_length(1);
+}
+
+/// Server without MOVE nor UIDPLUS, which mailboxes all contain messages 1, 2 and 3
+fn basic_server(command: &str) -> (String, String) {
+	if command.starts_with("CAPABILITY") {
+		("* CAPABILITY IMAP4rev1\r\n".to_string(), "OK CAPABILITY completed".to_string())
+	} else if command.starts_with("UID SEARCH") {
+		("* SEARCH 1 2 3\r\n".to_string(), "OK SEARCH completed".to_string())
+	} else {
+		imap_ok(command)
+	}
+}
+
+fn keeping_last(trash: Option<&str>) -> Retention {
+	Retention { max_age_days: None, max_count: Some(1), trash: trash.map(|t| t.to_string()) }
+}
+
+#[test]
+fn can_prune_without_uidplus() {
+	let (email, commands) = imap_server(basic_server);
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.prune("News", &keeping_last(None))).is_ok_containing(2);
+	assert_that!(received(&commands, "UID STORE")).is_equal_to(vec!["UID STORE 1,2 +FLAGS.SILENT (\\Deleted)".to_string()]);
+	assert_that!(received(&commands, "EXPUNGE")).has_length(1);
+	assert_that!(received(&commands, "UID EXPUNGE")).is_empty();
+}
+
+#[test]
+fn can_prune_to_trash_without_move() {
+	let (email, commands) = imap_server(basic_server);
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.prune("News", &keeping_last(Some("Trash")))).is_ok_containing(2);
+	assert_that!(received(&commands, "UID COPY")).is_equal_to(vec!["UID COPY 1,2 Trash".to_string()]);
+	assert_that!(received(&commands, "UID MOVE")).is_empty();
+	assert_that!(received(&commands, "EXPUNGE")).has_length(1);
+}
+
+#[test]
+fn cant_copy_messages_twice() {
+	let (email, commands) = imap_server(|command| if command.starts_with("EXPUNGE") {
+		(String::new(), "NO expunge failed".to_string())
+	} else {
+		basic_server(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.prune("News", &keeping_last(Some("Trash")))).is_err();
+	assert_that!(received(&commands, "UID COPY")).has_length(1);
+}
+
+/// Response to a fetch of the given header, giving the given values to messages 1, 2 and 3
+fn header_values(header: &str, values: [&str; 3]) -> String {
+	values.iter().enumerate()
+		.map(|(index, value)| {
+			let fields = format!("{}: {}\r\n\r\n", header, value);
+			format!("* {} FETCH (UID {} BODY[HEADER.FIELDS ({})] {{{}}}\r\n{})\r\n", index + 1, index + 1, header, fields.len(), fields)
+		})
+		.collect()
+}
+
+#[test]
+fn can_only_locate_message_having_exactly_the_message_id() {
+	let (email, _) = imap_server(|command| if command.starts_with("UID FETCH") {
+		(header_values("Message-ID", ["<entry@rrss2imap>", "<entry@rrss2imap>", "<other-entry@rrss2imap>"]), "OK FETCH completed".to_string())
+	} else {
+		basic_server(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.locate_message("News", "entry@rrss2imap")).is_ok_containing(Some((1, 2)));
+}
+
+#[test]
+fn can_replace_state_without_uidplus() {
+	let (email, commands) = imap_server(basic_server);
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.write_state("rrss2imap", &State::default(), Some(3))).is_ok();
+	assert_that!(received(&commands, "APPEND")).has_length(1);
+	assert_that!(received(&commands, "UID STORE")).has_length(1);
+	assert_that!(received(&commands, "EXPUNGE")).has_length(1);
+	assert_that!(received(&commands, "UID EXPUNGE")).is_empty();
+}
+
+#[test]
+fn cant_write_state_twice() {
+	let (email, commands) = imap_server(|command| if command.starts_with("APPEND") {
+		(String::new(), "NO append failed".to_string())
+	} else {
+		basic_server(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.write_state("rrss2imap", &State::default(), Some(3))).is_err();
+	assert_that!(received(&commands, "APPEND")).has_length(1);
+	assert_that!(received(&commands, "UID STORE")).is_empty();
+}
+
+#[test]
+fn can_move_feed_messages_without_move() {
+	let (email, commands) = imap_server(|command| if command.starts_with("UID FETCH") {
+		(header_values(FEED_HEADER, ["https://xkcd.com/atom.xml", "https://example.com/feed", "https://xkcd.com/atom.xml"]),
+			"OK FETCH completed".to_string())
+	} else {
+		basic_server(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.move_feed_messages("News", "Comics", "https://xkcd.com/atom.xml")).is_ok_containing(2);
+	assert_that!(received(&commands, "UID COPY")).is_equal_to(vec!["UID COPY 1,3 Comics".to_string()]);
+	assert_that!(received(&commands, "UID MOVE")).is_empty();
+	assert_that!(received(&commands, "EXPUNGE")).has_length(1);
+}
+
+#[test]
+fn cant_move_feed_messages_twice() {
+	let (email, commands) = imap_server(|command| if command.starts_with("UID FETCH") {
+		(header_values(FEED_HEADER, ["https://xkcd.com/atom.xml"; 3]), "OK FETCH completed".to_string())
+	} else if command.starts_with("EXPUNGE") {
+		(String::new(), "NO expunge failed".to_string())
+	} else {
+		basic_server(command)
+	});
+	let pool = SessionPool::new(&email);
+	assert_that!(pool.move_feed_messages("News", "Comics", "https://xkcd.com/atom.xml")).is_err();
+	assert_that!(received(&commands, "UID COPY")).has_length(1);
+}
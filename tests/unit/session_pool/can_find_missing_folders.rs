@@ -0,0 +1,37 @@
+use super::*;
+
+fn existing(folders: Vec<&str>) -> HashSet<String> {
+	folders.iter().map(|f| f.to_string()).collect()
+}
+
+#[test]
+fn can_find_nothing_for_existing_folder() {
+	assert_eq!(
+		Vec::<String>::new(),
+		missing_folders(&existing(vec!["RSS", "RSS/News"]), "RSS/News", &Some("/".to_string()))
+	)
+}
+
+#[test]
+fn can_find_missing_parents_first() {
+	assert_eq!(
+		vec!["RSS/New".to_string(), "RSS/New/Folder".to_string()],
+		missing_folders(&existing(vec!["INBOX", "RSS"]), "RSS/New/Folder", &Some("/".to_string()))
+	)
+}
+
+#[test]
+fn can_use_server_delimiter() {
+	assert_eq!(
+		vec!["INBOX.News".to_string()],
+		missing_folders(&existing(vec![]), "INBOX.News", &Some(".".to_string()))
+	)
+}
+
+#[test]
+fn can_handle_flat_servers() {
+	assert_eq!(
+		vec!["RSS/New".to_string()],
+		missing_folders(&existing(vec![]), "RSS/New", &None)
+	)
+}
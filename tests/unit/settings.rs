@@ -0,0 +1,128 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use std::env;
+use std::fs;
+use super::*;
+
+#[test]
+fn can_resolve_clear_text_password() {
+	assert_that!(Email::default().resolve_password())
+		.is_ok_containing("Set your imap server password (yup, in clear, this is very bad)".to_string());
+}
+
+#[test]
+fn can_resolve_password_from_environment() {
+	env::set_var("RRSS2IMAP_CAN_RESOLVE_PASSWORD_FROM_ENVIRONMENT", "from environment");
+	let email = Email {
+		password_env: Some("RRSS2IMAP_CAN_RESOLVE_PASSWORD_FROM_ENVIRONMENT".to_string()),
+		..Email::default()
+	};
+	assert_that!(email.resolve_password())
+		.is_ok_containing("from environment".to_string());
+}
+
+#[test]
+fn can_resolve_password_from_file() {
+	let mut file = env::temp_dir();
+	file.push("rrss2imap_can_resolve_password_from_file");
+	fs::write(&file, "from file\nsome other line\n").unwrap();
+	let email = Email {
+		password_file: Some(file),
+		..Email::default()
+	};
+	assert_that!(email.resolve_password())
+		.is_ok_containing("from file".to_string());
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn can_resolve_password_from_command() {
+	let email = Email {
+		password_command: Some("printf 'from command\\nlogin: user\\n'".to_string()),
+		password_file: Some("this file is not read".into()),
+		..Email::default()
+	};
+	assert_that!(email.resolve_password())
+		.is_ok_containing("from command".to_string());
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn can_fail_resolving_password_from_failing_command() {
+	let email = Email {
+		password_command: Some("exit 1".to_string()),
+		..Email::default()
+	};
+	assert_that!(email.resolve_password())
+		.is_err();
+}
+
+/// Writes a freshly generated self-signed certificate and its private key in a temp directory
+fn self_signed(test: &str) -> (PathBuf, PathBuf) {
+	use openssl::{asn1::Asn1Time, hash::MessageDigest, pkey::PKey, rsa::Rsa, x509::{X509, X509NameBuilder}};
+	let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+	let mut name = X509NameBuilder::new().unwrap();
+	name.append_entry_by_text("CN", "rrss2imap test").unwrap();
+	let name = name.build();
+	let mut cert = X509::builder().unwrap();
+	cert.set_version(2).unwrap();
+	cert.set_subject_name(&name).unwrap();
+	cert.set_issuer_name(&name).unwrap();
+	cert.set_pubkey(&key).unwrap();
+	cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+	cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+	cert.sign(&key, MessageDigest::sha256()).unwrap();
+	let mut directory = env::temp_dir();
+	directory.push(format!("rrss2imap_{}", test));
+	fs::create_dir_all(&directory).unwrap();
+	let cert_file = directory.join("client.pem");
+	let key_file = directory.join("client.key");
+	fs::write(&cert_file, cert.build().to_pem().unwrap()).unwrap();
+	fs::write(&key_file, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+	(cert_file, key_file)
+}
+
+#[test]
+fn can_build_tls_connector_with_custom_settings() {
+	let (cert, key) = self_signed("can_build_tls_connector_with_custom_settings");
+	let email = Email {
+		ca_file: Some(cert.clone()),
+		client_cert: Some(cert),
+		client_key: Some(key),
+		min_tls_version: Some(TlsVersion::Tls12),
+		danger_accept_invalid_certs: true,
+		..Email::default()
+	};
+	assert_that!(email.tls_connector().is_ok()).is_true();
+}
+
+#[test]
+fn can_refuse_invalid_ca_file() {
+	let (_, key) = self_signed("can_refuse_invalid_ca_file");
+	let email = Email {
+		ca_file: Some(key),
+		..Email::default()
+	};
+	assert_that!(email.tls_connector().is_err()).is_true();
+}
+
+#[test]
+fn can_refuse_invalid_client_key() {
+	let (cert, _) = self_signed("can_refuse_invalid_client_key");
+	let email = Email {
+		client_cert: Some(cert.clone()),
+		client_key: Some(cert),
+		..Email::default()
+	};
+	assert_that!(email.tls_connector().is_err()).is_true();
+}
+
+#[test]
+fn can_refuse_missing_ca_file() {
+	let email = Email {
+		ca_file: Some(PathBuf::from("tests/unit/settings/missing.pem")),
+		..Email::default()
+	};
+	assert_that!(email.tls_connector().is_err()).is_true();
+}
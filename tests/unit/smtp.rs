@@ -0,0 +1,122 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use super::*;
+
+/// Starts a minimal SMTP sink accepting one message, and returning the whole transcript
+fn smtp_sink(listener: TcpListener) -> thread::JoinHandle<Vec<String>> {
+	thread::spawn(move || {
+		let (stream, _) = listener.accept().unwrap();
+		let mut reader = BufReader::new(stream);
+		let mut transcript = vec![];
+		write!(reader.get_mut(), "220 localhost ESMTP sink\r\n").unwrap();
+		let mut in_data = false;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line).unwrap() == 0 {
+				break;
+			}
+			let line = line.trim_end().to_string();
+			transcript.push(line.clone());
+			let answer = if in_data {
+				if line == "." {
+					in_data = false;
+					"250 queued"
+				} else {
+					continue;
+				}
+			} else if line.starts_with("EHLO") {
+				"250-localhost\r\n250 AUTH PLAIN LOGIN"
+			} else if line.starts_with("AUTH") {
+				"235 authenticated"
+			} else if line.starts_with("DATA") {
+				in_data = true;
+				"354 go ahead"
+			} else if line.starts_with("QUIT") {
+				write!(reader.get_mut(), "221 bye\r\n").unwrap();
+				break;
+			} else {
+				"250 ok"
+			};
+			write!(reader.get_mut(), "{}\r\n", answer).unwrap();
+		}
+		transcript
+	})
+}
+
+#[test]
+fn can_send_message_to_local_smtp_sink() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let server = smtp_sink(listener);
+	let smtp = Smtp {
+		server: "127.0.0.1".to_string(),
+		secure: Secure::No(port),
+		user: Some("feeds@example.com".to_string()),
+		password: Some("password".to_string()),
+		sender: None,
+	};
+	let delivery = SmtpDelivery::new(&smtp, &None).unwrap();
+	let content = "From: author@example.com\r\nTo: me@example.com\r\nSubject: An entry\r\n\r\nEntry content\r\n";
+	let envelope = Envelope {
+		message_id: "entry@rrss2imap",
+		title: "An entry",
+		folder: "ignored",
+		recipient: "me@example.com",
+		content,
+		flags: &[],
+		date: None,
+	};
+	assert_that!(delivery.deliver(&envelope)).is_ok();
+	drop(delivery);
+	let transcript = server.join().unwrap();
+	assert_that!(transcript).contains("MAIL FROM:<feeds@example.com>".to_string());
+	assert_that!(transcript).contains("RCPT TO:<me@example.com>".to_string());
+	assert_that!(transcript).contains("Subject: An entry".to_string());
+	assert_that!(transcript.iter().any(|line| line.starts_with("AUTH"))).is_true();
+}
+
+#[test]
+fn can_send_message_to_recipient_with_display_name() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let server = smtp_sink(listener);
+	let smtp = Smtp {
+		server: "127.0.0.1".to_string(),
+		secure: Secure::No(port),
+		user: None,
+		password: None,
+		sender: Some("Feeds <feeds@example.com>".to_string()),
+	};
+	let delivery = SmtpDelivery::new(&smtp, &None).unwrap();
+	let content = "From: author@example.com\r\nTo: Sender <username@imap_server.com>\r\nSubject: An entry\r\n\r\nEntry content\r\n";
+	let envelope = Envelope {
+		message_id: "entry@rrss2imap",
+		title: "An entry",
+		folder: "ignored",
+		recipient: "Sender <username@imap_server.com>",
+		content,
+		flags: &[],
+		date: None,
+	};
+	assert_that!(delivery.deliver(&envelope)).is_ok();
+	drop(delivery);
+	let transcript = server.join().unwrap();
+	assert_that!(transcript).contains("MAIL FROM:<feeds@example.com>".to_string());
+	assert_that!(transcript).contains("RCPT TO:<username@imap_server.com>".to_string());
+}
+
+#[test]
+fn can_refuse_invalid_sender() {
+	let smtp = Smtp {
+		server: "127.0.0.1".to_string(),
+		secure: Secure::No(25),
+		user: None,
+		password: None,
+		sender: Some("not an address".to_string()),
+	};
+	assert_that!(SmtpDelivery::new(&smtp, &None).is_err()).is_true();
+}
@@ -26,14 +26,33 @@ fn can_read_store_from_existing_file() {
 		password: "password".to_string(),
 		secure: crate::settings::Secure::Yes(993),
 		retry_max_count: 3,
-		retry_delay: 1
+		retry_delay: 1,
+		create_missing_folders: true,
+		subscribe_created_folders: false,
+		check_duplicates: false,
+		auth: None,
+		password_command: None,
+		password_file: None,
+		password_env: None,
+		secrets: None,
+		ca_file: None,
+		client_cert: None,
+		client_key: None,
+		min_tls_version: None,
+		danger_accept_invalid_certs: false
 	});
 	assert_that!(store.settings.config)
 	.is_equal_to(Config {
 		email: Some("Sender <username@imap_server.com>".to_string()),
 		folder: Some("default_folder".to_string()),
 		from: None,
-		inline_image_as_data: true
+		inline_image_as_data: true,
+		flags: None,
+		delivery: None,
+		retention: None,
+		account: None,
+		track_updates: false,
+		mark_updates: false
 	});
 	assert_that!(store.feeds)
 		.has_length(1);
@@ -60,14 +79,33 @@ fn can_read_store_from_non_existing_file() {
 		password: "Set your imap server password (yup, in clear, this is very bad)".to_string(),
 		secure: crate::settings::Secure::Yes(993),
 		retry_max_count: 3,
-		retry_delay: 1
+		retry_delay: 1,
+		create_missing_folders: true,
+		subscribe_created_folders: false,
+		check_duplicates: false,
+		auth: None,
+		password_command: None,
+		password_file: None,
+		password_env: None,
+		secrets: None,
+		ca_file: None,
+		client_cert: None,
+		client_key: None,
+		min_tls_version: None,
+		danger_accept_invalid_certs: false
 	});
 	assert_that!(store.settings.config)
 	.is_equal_to(Config {
 		email: None,
 		folder: None,
 		from: None,
-		inline_image_as_data: false
+		inline_image_as_data: false,
+		flags: None,
+		delivery: None,
+		retention: None,
+		account: None,
+		track_updates: false,
+		mark_updates: false
 	});
 	assert_that!(store.feeds)
 	.is_equal_to(vec![])
@@ -90,13 +128,36 @@ fn bugfix_82_export_is_broken() {
 				password: "password".to_string(),
 				secure: crate::settings::Secure::Yes(993),
 				retry_max_count: 3,
-				retry_delay: 1
+				retry_delay: 1,
+				create_missing_folders: true,
+				subscribe_created_folders: false,
+				check_duplicates: false,
+				auth: None,
+				password_command: None,
+				password_file: None,
+				password_env: None,
+				secrets: None,
+				ca_file: None,
+				client_cert: None,
+				client_key: None,
+				min_tls_version: None,
+				danger_accept_invalid_certs: false
 			}, 
+			accounts: Default::default(),
+			smtp: None,
+			state_folder: None,
+			quota: None,
 			config: Config {
 				email: Some("Sender <username@imap_server.com>".to_string()),
 				folder: Some("default_folder".to_string()),
 				from: None,
-				inline_image_as_data: true
+				inline_image_as_data: true,
+				flags: None,
+				delivery: None,
+				retention: None,
+				account: None,
+				track_updates: false,
+				mark_updates: false
 			}
 		},
 		feeds: vec![],
@@ -111,7 +172,8 @@ fn bugfix_82_export_is_broken() {
 	// Finally, add one feed to the store
 	store.add_feed(Feed::from_vec(vec!["https://xkcd.com/rss.xml".to_string()]));
 	// When
-	store.export(Some(export_path.clone()));
+	assert_that!(store.export(Some(export_path.clone())))
+		.is_ok();
 	// Then
 	assert_that!(export_path)
 		.is_a_file();
@@ -119,4 +181,109 @@ fn bugfix_82_export_is_broken() {
 	let opml_content = fs::read_to_string(export_path).unwrap();
 	assert_that!(opml_content)
 		.contains("https://xkcd.com/rss.xml")
-}
\ No newline at end of file
+}
+#[test]
+fn can_read_store_with_accounts() {
+	let mut config_file = env::current_dir().unwrap();
+	config_file.push("tests");
+	config_file.push("unit");
+	config_file.push("store");
+	config_file.push("can_read_store_with_accounts.json");
+	let store = Store::load(&config_file).unwrap();
+	let servers: Vec<String> = store.feeds.iter()
+		.map(|feed| feed.config.get_account(&store.settings.config))
+		.map(|account| store.settings.account(&account).unwrap().server.clone())
+		.collect();
+	assert_that!(servers)
+		.is_equal_to(vec!["imap_server".to_string(), "other_imap_server".to_string()]);
+	assert_that!(store.settings.account(&Some("missing".to_string())))
+		.is_none();
+}
+
+#[test]
+fn cant_export_without_file() {
+	let mut store_path = env::current_dir().unwrap();
+	store_path.push("tests");
+	store_path.push("unit");
+	store_path.push("store");
+	store_path.push("cant_export_without_file.json");
+	let store = Store::load(&store_path).unwrap();
+	assert_that!(store.export(None).is_err()).is_true();
+}
+
+#[test]
+fn cant_delete_unknown_feed() {
+	let mut store_path = env::current_dir().unwrap();
+	store_path.push("tests");
+	store_path.push("unit");
+	store_path.push("store");
+	store_path.push("cant_delete_unknown_feed.json");
+	let mut store = Store::load(&store_path).unwrap();
+	store.add_feed(Feed::from_vec(vec!["https://xkcd.com/rss.xml".to_string()]));
+	assert_that!(store.delete(1).is_err()).is_true();
+	assert_that!(store.feeds.len()).is_equal_to(1);
+	assert_that!(store.delete(0).is_ok()).is_true();
+	assert_that!(store.feeds.len()).is_equal_to(0);
+	// Store file is not expected to be written
+	store.dirty = false;
+}
+
+#[test]
+fn cant_set_secret_of_unknown_account() {
+	let mut store_path = env::current_dir().unwrap();
+	store_path.push("tests");
+	store_path.push("unit");
+	store_path.push("store");
+	store_path.push("cant_set_secret_of_unknown_account.json");
+	let mut store = Store::load(&store_path).unwrap();
+	assert_that!(store.set_secret(Some("unknown".to_string()), "password".to_string(), Some("secret".to_string())).is_err())
+		.is_true();
+	assert_that!(store.dirty).is_false();
+}
+
+#[test]
+fn run_summary_tells_network_failures_from_partial_ones() {
+	let mut summary = RunSummary { feeds: 2, ..RunSummary::default() };
+	assert_that!(summary.is_success()).is_true();
+	summary.undelivered.push(("https://xkcd.com/rss.xml".to_string(), 3));
+	assert_that!(summary.is_success()).is_false();
+	assert_that!(summary.is_network_failure()).is_false();
+	summary.unreadable_feeds.push(("https://xkcd.com/atom.xml".to_string(), "timeout".to_string()));
+	assert_that!(summary.is_network_failure()).is_false();
+	summary.unreadable_feeds.push(("https://xkcd.com/rss.xml".to_string(), "timeout".to_string()));
+	assert_that!(summary.is_network_failure()).is_true();
+}
+
+#[test]
+fn run_summary_reports_messages_not_written_to_imap() {
+	let mut summary = RunSummary { feeds: 2, refused: 1, ..RunSummary::default() };
+	assert_that!(summary.is_success()).is_false();
+	assert_that!(summary.is_network_failure()).is_false();
+	summary.queued = 3;
+	assert_that!(summary.is_network_failure()).is_true();
+	let summary = RunSummary {
+		feeds: 2,
+		unflushed: Some(FailedImapCommand::NotFlushed { count: 2, path: "outbox".to_string() }),
+		..RunSummary::default()
+	};
+	assert_that!(summary.is_network_failure()).is_true();
+}
+
+#[test]
+fn can_move_feed_not_written_to_imap() {
+	let mut store_path = env::current_dir().unwrap();
+	store_path.push("tests");
+	store_path.push("unit");
+	store_path.push("store");
+	store_path.push("can_move_feed_not_written_to_imap.json");
+	let mut store = Store::load(&store_path).unwrap();
+	let mut feed = Feed::from_vec(vec!["https://xkcd.com/rss.xml".to_string()]);
+	feed.config.delivery = Some(crate::delivery::Target::Maildir(env::temp_dir()));
+	store.add_feed(feed);
+	assert_that!(store.move_feed(1, "Comics".to_string()).is_err()).is_true();
+	assert_that!(store.move_feed(0, "Comics".to_string()).is_ok()).is_true();
+	assert_that!(store.feeds[0].config.folder)
+		.is_equal_to(Some("Comics".to_string()));
+	// Don't write the store file
+	store.dirty = false;
+}
@@ -0,0 +1,42 @@
+extern crate spectral;
+use spectral::prelude::*;
+
+use crate::utf7::{decode, encode};
+
+#[test]
+fn can_keep_ascii_names_unchanged() {
+	assert_that!(encode("RSS/Comics")).is_equal_to("RSS/Comics".to_string());
+	assert_that!(decode("RSS/Comics")).is_some().is_equal_to("RSS/Comics".to_string());
+}
+
+#[test]
+fn can_escape_ampersand() {
+	assert_that!(encode("Tom & Jerry")).is_equal_to("Tom &- Jerry".to_string());
+	assert_that!(decode("Tom &- Jerry")).is_some().is_equal_to("Tom & Jerry".to_string());
+}
+
+#[test]
+fn can_encode_accented_names() {
+	assert_that!(encode("RSS/Actualités")).is_equal_to("RSS/Actualit&AOk-s".to_string());
+	assert_that!(encode("日本")).is_equal_to("&ZeVnLA-".to_string());
+}
+
+/// Example given in RFC 3501 §5.1.3
+#[test]
+fn can_encode_rfc_example() {
+	assert_that!(encode("~peter/mail/台北/日本語"))
+		.is_equal_to("~peter/mail/&U,BTFw-/&ZeVnLIqe-".to_string());
+}
+
+#[test]
+fn can_round_trip_names() {
+	for name in ["RSS/Actualités", "RSS/日本", "Été & hiver", "Emoji 🦀 crab", "café/thé"] {
+		assert_that!(decode(&encode(name))).is_some().is_equal_to(name.to_string());
+	}
+}
+
+#[test]
+fn cant_decode_invalid_names() {
+	assert_that!(decode("RSS/&ZeVnLA")).is_none();
+	assert_that!(decode("RSS/&Z-")).is_none();
+}